@@ -0,0 +1,325 @@
+//! A two-pass assembler for the textual format produced by the disassembler.
+//!
+//! Every line holds an optional address check (`01234:`), any number of
+//! labels (`loop:`), then an instruction or a directive:
+//!
+//! - instructions use the mnemonics of the opcode listing, with operands
+//!   separated by whitespace or commas: registers `r0`-`r7`, decimal or `0x`
+//!   hexadecimal numbers, character literals like `'a'` or label names
+//! - `out "text"` expands to one `out` instruction per character
+//! - `.data` (or `.word`) emits raw words, `.string "text"` emits one word per
+//!   character
+//!
+//! Everything after a `;` is a comment.
+
+use crate::opcode::Opcode;
+use std::{collections::HashMap, error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for AsmError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Char(char),
+}
+
+/// A word of the output, labels are resolved in the second pass.
+#[derive(Debug)]
+enum Emit {
+    Word(u16),
+    Label(String, usize),
+}
+
+/// Assembles the source into the words of a program.
+pub fn assemble(source: &str) -> Result<Vec<u16>, AsmError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut emitted: Vec<Emit> = Vec::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| AsmError { line, message };
+
+        let mut tokens = tokenize(text).map_err(error)?.into_iter().peekable();
+
+        while let Some(Token::Word(word)) = tokens.peek() {
+            let name = match word.strip_suffix(':') {
+                Some(name) => name.to_string(),
+                None => break,
+            };
+            tokens.next();
+
+            if name.chars().all(|c| c.is_ascii_digit()) {
+                let addr: usize = name
+                    .parse()
+                    .map_err(|_| error(format!("invalid address: {}", name)))?;
+                if addr != emitted.len() {
+                    return Err(error(format!(
+                        "expected address {} but got {}",
+                        emitted.len(),
+                        addr
+                    )));
+                }
+            } else if is_identifier(&name) {
+                if labels.insert(name.clone(), emitted.len()).is_some() {
+                    return Err(error(format!("duplicate label: {}", name)));
+                }
+            } else {
+                return Err(error(format!("invalid label: {}", name)));
+            }
+        }
+
+        let mnemonic = match tokens.next() {
+            Some(Token::Word(mnemonic)) => mnemonic,
+            Some(token) => return Err(error(format!("unexpected {:?}", token))),
+            None => continue,
+        };
+        let operands: Vec<Token> = tokens.collect();
+
+        match mnemonic.as_str() {
+            ".data" | ".word" => {
+                for operand in &operands {
+                    emitted.push(data(operand, line).map_err(error)?);
+                }
+            }
+            ".string" => match operands.as_slice() {
+                [Token::Str(text)] => {
+                    emitted.extend(text.chars().map(|c| Emit::Word(c as u16)));
+                }
+                _ => return Err(error(".string expects a single string".to_string())),
+            },
+            _ => {
+                let opcode = Opcode::from_name(&mnemonic)
+                    .ok_or_else(|| error(format!("unknown mnemonic: {}", mnemonic)))?;
+
+                if let (Opcode::Out, [Token::Str(text)]) = (opcode, operands.as_slice()) {
+                    for c in text.chars() {
+                        emitted.push(Emit::Word(opcode.code() as u16));
+                        emitted.push(Emit::Word(c as u16));
+                    }
+                    continue;
+                }
+
+                if operands.len() != opcode.arity() {
+                    return Err(error(format!(
+                        "{} expects {} operands but got {}",
+                        opcode,
+                        opcode.arity(),
+                        operands.len()
+                    )));
+                }
+                emitted.push(Emit::Word(opcode.code() as u16));
+                for operand in &operands {
+                    emitted.push(operand_word(operand, line).map_err(error)?);
+                }
+            }
+        }
+    }
+
+    emitted
+        .into_iter()
+        .map(|emit| match emit {
+            Emit::Word(word) => Ok(word),
+            Emit::Label(name, line) => {
+                labels
+                    .get(&name)
+                    .map(|addr| *addr as u16)
+                    .ok_or_else(|| AsmError {
+                        line,
+                        message: format!("undefined label: {}", name),
+                    })
+            }
+        })
+        .collect()
+}
+
+fn operand_word(token: &Token, line: usize) -> Result<Emit, String> {
+    match token {
+        Token::Word(word) => {
+            if let Some(register) = register(word) {
+                Ok(Emit::Word(32768 + register))
+            } else if is_identifier(word) {
+                Ok(Emit::Label(word.clone(), line))
+            } else {
+                let number = number(word)?;
+                if number < 32768 {
+                    Ok(Emit::Word(number))
+                } else {
+                    Err(format!("operand out of range: {}", word))
+                }
+            }
+        }
+        Token::Char(c) => Ok(Emit::Word(*c as u16)),
+        Token::Str(_) => Err("unexpected string operand".to_string()),
+    }
+}
+
+fn data(token: &Token, line: usize) -> Result<Emit, String> {
+    match token {
+        Token::Word(word) if is_identifier(word) && register(word).is_none() => {
+            Ok(Emit::Label(word.clone(), line))
+        }
+        Token::Word(word) => match register(word) {
+            Some(register) => Ok(Emit::Word(32768 + register)),
+            None => Ok(Emit::Word(number(word)?)),
+        },
+        Token::Char(c) => Ok(Emit::Word(*c as u16)),
+        Token::Str(_) => Err("use .string for strings".to_string()),
+    }
+}
+
+fn register(word: &str) -> Option<u16> {
+    match word.strip_prefix('r')?.parse() {
+        Ok(register) if register < 8 => Some(register),
+        _ => None,
+    }
+}
+
+fn number(word: &str) -> Result<u16, String> {
+    let result = match word.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    result.map_err(|_| format!("invalid number: {}", word))
+}
+
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() || c == ',' => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.push(escape(chars.next())?),
+                        Some(c) => text.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(text));
+            }
+            '\'' => {
+                chars.next();
+                let c = match chars.next() {
+                    Some('\\') => escape(chars.next())?,
+                    Some(c) => c,
+                    None => return Err("unterminated character".to_string()),
+                };
+                if chars.next() != Some('\'') {
+                    return Err("unterminated character".to_string());
+                }
+                tokens.push(Token::Char(c));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ',' || c == ';' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn escape(c: Option<char>) -> Result<char, String> {
+    match c {
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('0') => Ok('\0'),
+        Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => Ok(c),
+        Some(c) => Err(format!("unknown escape: \\{}", c)),
+        None => Err("unterminated escape".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary, disasm::disassemble};
+
+    #[test]
+    fn arch_spec_example() {
+        assert_eq!(
+            assemble("add r0, r1, 4\nout r0").unwrap(),
+            vec![9, 32768, 32769, 4, 19, 32768]
+        );
+    }
+
+    #[test]
+    fn labels_and_literals() {
+        let source = "
+            start:  set r0 'a'      ; comment
+                    jmp end
+            .string \"hi\"
+            end:    out \"a;b\"
+                    .data 0xffff, start, r7, '\\n'
+        ";
+
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![1, 32768, 97, 6, 7, 104, 105, 19, 97, 19, 59, 19, 98, 65535, 0, 32775, 10]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("noop\nfoo r0").unwrap_err(),
+            AsmError {
+                line: 2,
+                message: "unknown mnemonic: foo".to_string()
+            }
+        );
+        assert_eq!(assemble("add r0 1").unwrap_err().line, 1);
+        assert_eq!(assemble("jmp nowhere").unwrap_err().line, 1);
+        assert_eq!(assemble("set r0 32768").unwrap_err().line, 1);
+        assert_eq!(assemble("noop\n00000: noop").unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn round_trips_the_challenge_binary() {
+        let program = binary::challenge();
+        let source: Vec<String> = disassemble(&program, 0)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+
+        assert_eq!(assemble(&source.join("\n")).unwrap(), program);
+    }
+}
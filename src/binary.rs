@@ -0,0 +1,35 @@
+use std::io::{self, BufReader, Read, Write};
+
+/// Reads a program stored as 16-bit little-endian words. A trailing odd byte
+/// is ignored.
+pub fn read_binary<R: Read>(reader: R) -> io::Result<Vec<u16>> {
+    let buf = BufReader::new(reader);
+    let mut result: Vec<u16> = Vec::new();
+
+    let mut iter = buf.bytes();
+
+    while let Some(low_byte_r) = iter.next() {
+        if let Some(high_byte_r) = iter.next() {
+            let low_byte = low_byte_r?;
+            let high_byte = high_byte_r?;
+
+            result.push(((high_byte as u16) << 8) | low_byte as u16)
+        }
+    }
+    Ok(result)
+}
+
+/// The program of the challenge, for the tests that run it.
+#[cfg(test)]
+pub(crate) fn challenge() -> Vec<u16> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/challenge/challenge.bin");
+    read_binary(std::fs::File::open(path).unwrap()).unwrap()
+}
+
+/// Writes a program in the format read by `read_binary`.
+pub fn write_binary<W: Write>(mut writer: W, program: &[u16]) -> io::Result<()> {
+    for word in program {
+        writer.write_all(&word.to_le_bytes())?;
+    }
+    writer.flush()
+}
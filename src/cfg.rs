@@ -0,0 +1,622 @@
+//! Control-flow graphs and the call graph of a program.
+//!
+//! The analysis follows the control flow from the entry points, decoding
+//! only the instructions that can be reached through constant jump and call
+//! targets, or through registers set to the same constant on every path there.
+//! Every other word is treated as data, which includes code that is only
+//! reached through a table or is still encrypted. The targets of `call`
+//! instructions are the entries of the functions.
+
+use crate::{
+    disasm,
+    opcode::Opcode,
+    vm::{Instruction, Value},
+};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    io::{self, Write},
+    ops::Range,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    Jump,
+    /// Taken when the condition of a `jt` or `jf` holds.
+    Taken,
+    /// Runs into the next block, after a conditional jump that was not taken
+    /// or when the next instruction is the target of a jump.
+    Fallthrough,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// Address right after the last instruction.
+    pub end: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    pub successors: Vec<Edge>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    /// Starts of the blocks reachable from the entry without calls.
+    pub blocks: BTreeSet<usize>,
+    pub calls: BTreeSet<usize>,
+    /// Whether the function calls an address that could not be resolved.
+    pub indirect_calls: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Analysis {
+    pub blocks: BTreeMap<usize, Block>,
+    pub functions: BTreeMap<usize, Function>,
+    /// Constant `wmem` targets with the addresses of the writes.
+    pub writes: BTreeMap<usize, Vec<usize>>,
+    /// `wmem` instructions that write to an address held in a register.
+    pub dynamic_writes: Vec<usize>,
+    /// Jumps to a register that does not hold the same constant on every
+    /// path to the jump.
+    pub dynamic_jumps: Vec<usize>,
+    /// Ranges of words that no reachable instruction covers.
+    pub data: Vec<Range<usize>>,
+}
+
+/// Analyzes the code reachable from the entry points.
+pub fn analyze(memory: &[u16], entries: &[usize]) -> Analysis {
+    let (instructions, targets) = discover(memory, entries);
+
+    let mut leaders: BTreeSet<usize> = entries
+        .iter()
+        .copied()
+        .filter(|addr| instructions.contains_key(addr))
+        .collect();
+    let mut function_entries = leaders.clone();
+    for (&addr, instruction) in &instructions {
+        let next = addr + instruction.opcode.width();
+        // targets that do not decode are left to the data
+        if let Some(&target) = targets
+            .get(&addr)
+            .filter(|target| instructions.contains_key(target))
+        {
+            leaders.insert(target);
+            if instruction.opcode == Opcode::Call {
+                function_entries.insert(target);
+            }
+        }
+        if ends_block(instruction.opcode) && instructions.contains_key(&next) {
+            leaders.insert(next);
+        }
+    }
+
+    let mut analysis = Analysis::default();
+    for &start in &leaders {
+        let block = block(&instructions, &targets, &leaders, start);
+        analysis.blocks.insert(start, block);
+    }
+    for entry in function_entries {
+        let function = function(&analysis.blocks, &targets, entry);
+        analysis.functions.insert(entry, function);
+    }
+
+    for (&addr, instruction) in &instructions {
+        match instruction.opcode {
+            Opcode::Wmem => match instruction.operands[0] {
+                Value::Number(target) => analysis.writes.entry(target).or_default().push(addr),
+                Value::Register(_) => analysis.dynamic_writes.push(addr),
+            },
+            Opcode::Jmp | Opcode::Jt | Opcode::Jf if !targets.contains_key(&addr) => {
+                analysis.dynamic_jumps.push(addr)
+            }
+            _ => {}
+        }
+    }
+
+    let mut covered = vec![false; memory.len()];
+    for (&addr, instruction) in &instructions {
+        let end = (addr + instruction.opcode.width()).min(memory.len());
+        covered[addr..end].iter_mut().for_each(|word| *word = true);
+    }
+    let mut addr = 0;
+    while addr < memory.len() {
+        let start = addr;
+        while addr < memory.len() && !covered[addr] {
+            addr += 1;
+        }
+        if addr > start {
+            analysis.data.push(start..addr);
+        }
+        addr += 1;
+    }
+
+    analysis
+}
+
+/// The registers known to hold a constant, `None` for the others.
+type Registers = [Option<usize>; 8];
+
+/// Decodes every instruction reachable from the entry points, returning
+/// them with the targets of the jumps and calls that could be resolved.
+///
+/// Where paths meet, a register keeps its constant only if every path agrees
+/// on it, and the instructions after the join are visited again whenever
+/// that takes a constant away.
+fn discover(
+    memory: &[u16],
+    entries: &[usize],
+) -> (BTreeMap<usize, Instruction>, BTreeMap<usize, usize>) {
+    let mut instructions = BTreeMap::new();
+    let mut states: BTreeMap<usize, Registers> = BTreeMap::new();
+    // lowest address first, so straight-line paths tend to meet before the
+    // code after the join is visited
+    let mut pending = BTreeSet::new();
+    for &entry in entries {
+        arrive(&mut states, &mut pending, entry, [None; 8]);
+    }
+
+    while let Some(addr) = pending.pop_first() {
+        let instruction = match instructions.get(&addr).cloned() {
+            Some(instruction) => instruction,
+            None if addr < memory.len() => match disasm::decode(memory, addr) {
+                Some(instruction) => instruction,
+                None => continue,
+            },
+            None => continue,
+        };
+        let mut registers = states[&addr];
+        if let Some(target) = target(&instruction, &registers) {
+            // a routine is called with whatever its callers leave in the registers
+            let entry = match instruction.opcode {
+                Opcode::Call => [None; 8],
+                _ => registers,
+            };
+            arrive(&mut states, &mut pending, target, entry);
+        }
+        match (instruction.opcode, instruction.operands.first()) {
+            (Opcode::Set, Some(Value::Register(r))) => {
+                registers[*r] = match instruction.operands[1] {
+                    Value::Number(n) => Some(n),
+                    Value::Register(source) => registers[source],
+                }
+            }
+            // the called routine may change any register
+            (Opcode::Call, _) => registers = [None; 8],
+            (opcode, Some(Value::Register(r))) if writes_register(opcode) => registers[*r] = None,
+            _ => {}
+        }
+        if falls_through(instruction.opcode) {
+            let next = addr + instruction.opcode.width();
+            arrive(&mut states, &mut pending, next, registers);
+        }
+        instructions.insert(addr, instruction);
+    }
+
+    let targets = instructions
+        .iter()
+        .filter_map(|(&addr, instruction)| Some((addr, target(instruction, &states[&addr])?)))
+        .collect();
+    (instructions, targets)
+}
+
+/// Merges the registers on one more path to `addr` into the state there,
+/// queueing the address when the state changes.
+fn arrive(
+    states: &mut BTreeMap<usize, Registers>,
+    pending: &mut BTreeSet<usize>,
+    addr: usize,
+    registers: Registers,
+) {
+    match states.entry(addr) {
+        Entry::Vacant(entry) => {
+            entry.insert(registers);
+        }
+        Entry::Occupied(mut entry) => {
+            let state = entry.get_mut();
+            let merged: Registers = std::array::from_fn(|r| match (state[r], registers[r]) {
+                (Some(a), Some(b)) if a == b => Some(a),
+                _ => None,
+            });
+            if merged == *state {
+                return;
+            }
+            *state = merged;
+        }
+    }
+    pending.insert(addr);
+}
+
+/// The target of a jump or call, when it is a constant or a register known
+/// to hold one.
+fn target(instruction: &Instruction, registers: &[Option<usize>; 8]) -> Option<usize> {
+    let operand = match (instruction.opcode, instruction.operands.as_slice()) {
+        (Opcode::Jmp, [target]) | (Opcode::Call, [target]) => target,
+        (Opcode::Jt, [_, target]) | (Opcode::Jf, [_, target]) => target,
+        _ => return None,
+    };
+    match operand {
+        Value::Number(target) => Some(*target),
+        Value::Register(r) => registers[*r],
+    }
+}
+
+fn writes_register(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Set
+            | Opcode::Pop
+            | Opcode::Eq
+            | Opcode::Gt
+            | Opcode::Add
+            | Opcode::Mult
+            | Opcode::Mod
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Not
+            | Opcode::Rmem
+            | Opcode::In
+    )
+}
+
+fn falls_through(opcode: Opcode) -> bool {
+    !matches!(opcode, Opcode::Jmp | Opcode::Ret | Opcode::Halt)
+}
+
+fn ends_block(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::Jmp | Opcode::Jt | Opcode::Jf | Opcode::Ret | Opcode::Halt
+    )
+}
+
+fn block(
+    instructions: &BTreeMap<usize, Instruction>,
+    targets: &BTreeMap<usize, usize>,
+    leaders: &BTreeSet<usize>,
+    start: usize,
+) -> Block {
+    let mut block = Block {
+        start,
+        end: start,
+        instructions: vec![],
+        successors: vec![],
+    };
+
+    while let Some(instruction) = instructions.get(&block.end) {
+        let addr = block.end;
+        block.end += instruction.opcode.width();
+        block.instructions.push((addr, instruction.clone()));
+
+        if ends_block(instruction.opcode) {
+            let kind = match instruction.opcode {
+                Opcode::Jmp => EdgeKind::Jump,
+                _ => EdgeKind::Taken,
+            };
+            if let Some(&target) = targets.get(&addr) {
+                block.successors.push(Edge { target, kind });
+            }
+            if falls_through(instruction.opcode) {
+                block.successors.push(Edge {
+                    target: block.end,
+                    kind: EdgeKind::Fallthrough,
+                });
+            }
+            break;
+        }
+        if leaders.contains(&block.end) {
+            block.successors.push(Edge {
+                target: block.end,
+                kind: EdgeKind::Fallthrough,
+            });
+            break;
+        }
+    }
+    block
+}
+
+fn function(
+    blocks: &BTreeMap<usize, Block>,
+    targets: &BTreeMap<usize, usize>,
+    entry: usize,
+) -> Function {
+    let mut function = Function {
+        entry,
+        blocks: BTreeSet::new(),
+        calls: BTreeSet::new(),
+        indirect_calls: false,
+    };
+    let mut pending = vec![entry];
+
+    while let Some(start) = pending.pop() {
+        let block = match blocks.get(&start) {
+            Some(block) if function.blocks.insert(start) => block,
+            _ => continue,
+        };
+        for (addr, instruction) in &block.instructions {
+            if instruction.opcode == Opcode::Call {
+                match targets.get(addr) {
+                    Some(&target) => {
+                        function.calls.insert(target);
+                    }
+                    None => function.indirect_calls = true,
+                }
+            }
+        }
+        pending.extend(block.successors.iter().map(|edge| edge.target));
+    }
+    function
+}
+
+impl Analysis {
+    /// Whether a `wmem` with a constant address writes into the block.
+    pub fn self_modified(&self, block: &Block) -> bool {
+        self.writes.range(block.start..block.end).next().is_some()
+    }
+
+    /// Writes the blocks of one function, or of the whole program, as a
+    /// Graphviz digraph. Blocks that are overwritten by `wmem` are filled.
+    pub fn write_cfg_dot<W: Write>(&self, mut out: W, function: Option<usize>) -> io::Result<()> {
+        let blocks: Vec<&Block> = match function {
+            Some(entry) => match self.functions.get(&entry) {
+                Some(function) => function
+                    .blocks
+                    .iter()
+                    .filter_map(|start| self.blocks.get(start))
+                    .collect(),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("no function at {:05}", entry),
+                    ))
+                }
+            },
+            None => self.blocks.values().collect(),
+        };
+
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for block in &blocks {
+            let mut label = String::new();
+            if self.functions.contains_key(&block.start) {
+                label.push_str(&format!("function {:05}\\l", block.start));
+            }
+            for (addr, instruction) in &block.instructions {
+                let line = format!("{:05}: {}", addr, instruction);
+                label.push_str(&line.replace('\\', "\\\\").replace('"', "\\\""));
+                label.push_str("\\l");
+            }
+            let style = if self.self_modified(block) {
+                ", style=filled, fillcolor=lightpink"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    b{:05} [label=\"{}\"{}];",
+                block.start, label, style
+            )?;
+        }
+        for block in &blocks {
+            for edge in &block.successors {
+                let attributes = match edge.kind {
+                    EdgeKind::Jump => "",
+                    EdgeKind::Taken => " [label=\"taken\"]",
+                    EdgeKind::Fallthrough => " [style=dashed]",
+                };
+                writeln!(
+                    out,
+                    "    b{:05} -> b{:05}{};",
+                    block.start, edge.target, attributes
+                )?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Writes the functions and the calls between them as a Graphviz
+    /// digraph, calls through a register lead to a shared node.
+    pub fn write_call_graph_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "digraph calls {{")?;
+        for entry in self.functions.keys() {
+            writeln!(out, "    f{:05} [label=\"{:05}\"];", entry, entry)?;
+        }
+        if self
+            .functions
+            .values()
+            .any(|function| function.indirect_calls)
+        {
+            writeln!(out, "    indirect [label=\"(register)\", shape=plaintext];")?;
+        }
+        for function in self.functions.values() {
+            for target in &function.calls {
+                writeln!(out, "    f{:05} -> f{:05};", function.entry, target)?;
+            }
+            if function.indirect_calls {
+                writeln!(
+                    out,
+                    "    f{:05} -> indirect [style=dashed];",
+                    function.entry
+                )?;
+            }
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Writes the number of functions and blocks, the writes into code and
+    /// the data ranges.
+    pub fn write_summary<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(
+            out,
+            "{} functions, {} blocks",
+            self.functions.len(),
+            self.blocks.len()
+        )?;
+        for block in self.blocks.values() {
+            for (target, writers) in self.writes.range(block.start..block.end) {
+                let writers: Vec<String> = writers.iter().map(|w| format!("{:05}", w)).collect();
+                writeln!(
+                    out,
+                    "code at {:05} is overwritten by wmem at {}",
+                    target,
+                    writers.join(", ")
+                )?;
+            }
+        }
+        if !self.dynamic_jumps.is_empty() {
+            writeln!(
+                out,
+                "{} jumps to computed addresses",
+                self.dynamic_jumps.len()
+            )?;
+        }
+        if !self.dynamic_writes.is_empty() {
+            writeln!(
+                out,
+                "{} wmem instructions write to computed addresses",
+                self.dynamic_writes.len()
+            )?;
+        }
+        for range in &self.data {
+            writeln!(
+                out,
+                "data {:05}-{:05} ({} words)",
+                range.start,
+                range.end - 1,
+                range.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary, strings};
+
+    const R0: u16 = 32768;
+    const R1: u16 = 32769;
+
+    // 0: call 6; 2: jt r0 0; 5: halt; 6: wmem 5 0; 9: ret; 10: .word 42
+    const PROGRAM: [u16; 11] = [17, 6, 7, R0, 0, 0, 16, 5, 0, 18, 42];
+
+    #[test]
+    fn blocks_and_functions() {
+        let analysis = analyze(&PROGRAM, &[0]);
+        let starts: Vec<usize> = analysis.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 5, 6]);
+        assert_eq!(
+            analysis.blocks[&0].successors,
+            vec![
+                Edge {
+                    target: 0,
+                    kind: EdgeKind::Taken
+                },
+                Edge {
+                    target: 5,
+                    kind: EdgeKind::Fallthrough
+                }
+            ]
+        );
+        assert_eq!(analysis.functions[&0].blocks, BTreeSet::from([0, 5]));
+        assert_eq!(analysis.functions[&0].calls, BTreeSet::from([6]));
+        assert_eq!(analysis.writes[&5], vec![6]);
+        assert!(analysis.self_modified(&analysis.blocks[&5]));
+        assert_eq!(analysis.data, vec![10..11]);
+
+        let mut dot = vec![];
+        analysis.write_call_graph_dot(&mut dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph calls {\n    \
+             f00000 [label=\"00000\"];\n    \
+             f00006 [label=\"00006\"];\n    \
+             f00000 -> f00006;\n}\n"
+        );
+    }
+
+    #[test]
+    fn registers_merge_where_paths_meet() {
+        // 0: set r1 100; 3: jt r0 9; 6: set r1 <second>; 9: jmp r1
+        let program = |second: u16| {
+            let mut program = vec![1, R1, 100, 7, R0, 9, 1, R1, second, 6, R1];
+            program.resize(201, 0);
+            program
+        };
+
+        let analysis = analyze(&program(200), &[0]);
+        assert!(analysis.blocks[&9].successors.is_empty());
+        assert_eq!(analysis.dynamic_jumps, vec![9]);
+        assert!(!analysis.blocks.contains_key(&100));
+        assert!(!analysis.blocks.contains_key(&200));
+
+        let analysis = analyze(&program(100), &[0]);
+        assert_eq!(
+            analysis.blocks[&9].successors,
+            vec![Edge {
+                target: 100,
+                kind: EdgeKind::Jump
+            }]
+        );
+        assert!(analysis.dynamic_jumps.is_empty());
+    }
+
+    #[test]
+    fn control_flow_dot() {
+        let analysis = analyze(&PROGRAM, &[0]);
+        let mut dot = vec![];
+        analysis.write_cfg_dot(&mut dot, Some(6)).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph cfg {\n    \
+             node [shape=box, fontname=monospace];\n    \
+             b00006 [label=\"function 00006\\l00006: wmem 5 0\\l00009: ret\\l\"];\n}\n"
+        );
+
+        let mut summary = vec![];
+        analysis.write_summary(&mut summary).unwrap();
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            "2 functions, 3 blocks\n\
+             code at 00005 is overwritten by wmem at 00006\n\
+             data 00010-00010 (1 words)\n"
+        );
+
+        // 5 is not the entry of a function
+        assert!(analysis.write_cfg_dot(vec![], Some(5)).is_err());
+    }
+
+    #[test]
+    fn undecodable_targets() {
+        // 0: jt r0 5; 3: halt; 4: halt; 5: .word 30000
+        let analysis = analyze(&[7, R0, 5, 0, 0, 30000], &[0]);
+        let starts: Vec<usize> = analysis.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 3]);
+        assert_eq!(analysis.data, vec![4..6]);
+    }
+
+    #[test]
+    fn teleporter_routine() {
+        let program = binary::challenge();
+        let memory = strings::decrypt(&program).unwrap();
+        // the handler of the teleporter is only reachable through a table
+        let analysis = analyze(&memory, &[0, 5445]);
+
+        // the self-test calls through a register set just before
+        assert!(analysis.functions[&1285].calls.contains(&1287));
+
+        let confirmation = &analysis.functions[&6027];
+        assert!(confirmation.calls.contains(&6027));
+        assert_eq!(confirmation.blocks.len(), 5);
+        assert!(analysis.functions[&1458].indirect_calls);
+        assert!(analysis
+            .functions
+            .values()
+            .any(|function| function.calls.contains(&6027)));
+    }
+}
@@ -0,0 +1,354 @@
+use log::LevelFilter;
+use std::path::PathBuf;
+use synacor_challenge_rs::{
+    explore::MapFormat,
+    opcode::Opcode,
+    trace::{TraceFilter, TraceFormat},
+};
+
+pub const USAGE: &str = "\
+Usage: synacor-challenge-rs [OPTIONS] [COMMAND] [PROGRAM]
+
+Commands:
+  run      run the program (default)
+  disasm   print the disassembled program
+  debug    run the program in the interactive debugger
+  trace    run the program and record every executed instruction
+  asm      assemble PROGRAM, a source file, into --output
+  walkthrough
+           play the --walkthrough script and check the output of the program
+  patch    apply the --patch files to PROGRAM and write the result to --output
+  teleporter
+           find the value of r7 that passes the teleporter confirmation
+  coins    print the moves that solve the coin puzzle, use --input-script or
+           --load-snapshot to get the coins into the inventory first
+  cfg      print the control-flow graph as DOT, with a summary of the writes
+           into code and the data ranges
+  calls    print the call graph as DOT
+  strings  decrypt PROGRAM without running it and print its text with addresses
+  vault    print the moves that bring the orb to the vault door
+  explore  visit every room reachable from the start and print a map
+
+Options:
+  --log-file <FILE>       file to write the log to [default: vm.log]
+  --log-level <LEVEL>     off, error, warn, info, debug or trace [default: warn]
+  --input-script <FILE>   feed the lines of FILE to the program before reading the terminal
+  --max-steps <N>         stop after executing N instructions
+  --load-snapshot <FILE>  restore the VM from a snapshot before running
+  --patch <FILE>          apply the patches in FILE, can be repeated
+  --walkthrough <FILE>    script of the walkthrough command
+  --codes <FILE>          write the codes printed by the run and trace commands to FILE
+  --max-rooms <N>         stop exploring after N rooms [default: 500]
+  --map-format <FORMAT>   dot or json [default: dot]
+  --function <ADDR>       analyze the function at ADDR too and only print its graph
+  --decrypt               emulate the decryption routine before the analysis
+  -o, --output <FILE>     output of the asm and patch commands [default: a.bin]
+  --trace-output <FILE>   file to write the trace to [default: trace.jsonl]
+  --trace-format <FORMAT> json or binary [default: json]
+  --trace-range <A-B>     only trace instructions at addresses A to B
+  --trace-opcodes <LIST>  only trace the comma separated opcodes, e.g. call,ret
+  -h, --help              print this help
+
+PROGRAM defaults to challenge/challenge.bin.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Disasm,
+    Debug,
+    Trace,
+    Asm,
+    Patch,
+    Teleporter,
+    Walkthrough,
+    Explore,
+    Coins,
+    Vault,
+    Strings,
+    Cfg,
+    Calls,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub program: PathBuf,
+    pub log_file: PathBuf,
+    pub log_level: LevelFilter,
+    pub input_script: Option<PathBuf>,
+    pub max_steps: Option<u64>,
+    pub load_snapshot: Option<PathBuf>,
+    pub patches: Vec<PathBuf>,
+    pub walkthrough: Option<PathBuf>,
+    pub codes_report: Option<PathBuf>,
+    pub max_rooms: usize,
+    pub map_format: MapFormat,
+    pub function: Option<usize>,
+    pub decrypt: bool,
+    pub output: PathBuf,
+    pub trace_output: PathBuf,
+    pub trace_format: TraceFormat,
+    pub trace_filter: TraceFilter,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Run,
+            program: PathBuf::from("challenge/challenge.bin"),
+            log_file: PathBuf::from("vm.log"),
+            log_level: LevelFilter::Warn,
+            input_script: None,
+            max_steps: None,
+            load_snapshot: None,
+            patches: vec![],
+            walkthrough: None,
+            codes_report: None,
+            max_rooms: 500,
+            map_format: MapFormat::Dot,
+            function: None,
+            decrypt: false,
+            output: PathBuf::from("a.bin"),
+            trace_output: PathBuf::from("trace.jsonl"),
+            trace_format: TraceFormat::JsonLines,
+            trace_filter: TraceFilter::default(),
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for {}", name))
+            };
+            match arg.as_str() {
+                "--log-file" => options.log_file = value(&arg)?.into(),
+                "--log-level" => {
+                    let level = value(&arg)?;
+                    options.log_level = level
+                        .parse()
+                        .map_err(|_| format!("invalid log level: {}", level))?;
+                }
+                "--input-script" => options.input_script = Some(value(&arg)?.into()),
+                "--max-steps" => {
+                    let steps = value(&arg)?;
+                    options.max_steps = Some(
+                        steps
+                            .parse()
+                            .map_err(|_| format!("invalid number of steps: {}", steps))?,
+                    );
+                }
+                "--load-snapshot" => options.load_snapshot = Some(value(&arg)?.into()),
+                "--patch" => options.patches.push(value(&arg)?.into()),
+                "--walkthrough" => options.walkthrough = Some(value(&arg)?.into()),
+                "--codes" => options.codes_report = Some(value(&arg)?.into()),
+                "--max-rooms" => {
+                    let rooms = value(&arg)?;
+                    options.max_rooms = rooms
+                        .parse()
+                        .map_err(|_| format!("invalid number of rooms: {}", rooms))?;
+                }
+                "--map-format" => {
+                    options.map_format = match value(&arg)?.as_str() {
+                        "dot" => MapFormat::Dot,
+                        "json" => MapFormat::Json,
+                        format => return Err(format!("invalid map format: {}", format)),
+                    }
+                }
+                "--function" => {
+                    let addr = value(&arg)?;
+                    options.function = Some(
+                        addr.parse()
+                            .map_err(|_| format!("invalid address: {}", addr))?,
+                    );
+                }
+                "--decrypt" => options.decrypt = true,
+                "-o" | "--output" => options.output = value(&arg)?.into(),
+                "--trace-output" => options.trace_output = value(&arg)?.into(),
+                "--trace-format" => {
+                    options.trace_format = match value(&arg)?.as_str() {
+                        "json" => TraceFormat::JsonLines,
+                        "binary" => TraceFormat::Binary,
+                        format => return Err(format!("invalid trace format: {}", format)),
+                    }
+                }
+                "--trace-range" => {
+                    let range = value(&arg)?;
+                    let bounds = range
+                        .split_once('-')
+                        .and_then(|(start, end)| Some((start.parse().ok()?, end.parse().ok()?)));
+                    match bounds {
+                        Some((start, end)) => options.trace_filter.addresses = Some(start..=end),
+                        None => return Err(format!("invalid address range: {}", range)),
+                    }
+                }
+                "--trace-opcodes" => {
+                    let opcodes = value(&arg)?
+                        .split(',')
+                        .map(|name| {
+                            Opcode::from_name(name)
+                                .ok_or_else(|| format!("unknown opcode: {}", name))
+                        })
+                        .collect::<Result<_, _>>()?;
+                    options.trace_filter.opcodes = Some(opcodes);
+                }
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter().peekable();
+        if let Some(command) = positional.peek().and_then(|arg| command(arg)) {
+            options.command = command;
+            positional.next();
+        }
+        if let Some(program) = positional.next() {
+            options.program = program.into();
+        }
+        if let Some(arg) = positional.next() {
+            return Err(format!("unexpected argument: {}", arg));
+        }
+
+        Ok(options)
+    }
+}
+
+fn command(name: &str) -> Option<Command> {
+    match name {
+        "run" => Some(Command::Run),
+        "disasm" => Some(Command::Disasm),
+        "debug" => Some(Command::Debug),
+        "trace" => Some(Command::Trace),
+        "asm" => Some(Command::Asm),
+        "patch" => Some(Command::Patch),
+        "teleporter" => Some(Command::Teleporter),
+        "walkthrough" => Some(Command::Walkthrough),
+        "explore" => Some(Command::Explore),
+        "coins" => Some(Command::Coins),
+        "vault" => Some(Command::Vault),
+        "strings" => Some(Command::Strings),
+        "cfg" => Some(Command::Cfg),
+        "calls" => Some(Command::Calls),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse(&[]).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn command_and_program() {
+        let options = parse(&["disasm", "patched.bin"]).unwrap();
+        assert_eq!(options.command, Command::Disasm);
+        assert_eq!(options.program, PathBuf::from("patched.bin"));
+
+        let options = parse(&["patched.bin"]).unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.program, PathBuf::from("patched.bin"));
+    }
+
+    #[test]
+    fn options() {
+        let options = parse(&[
+            "--log-level",
+            "debug",
+            "trace",
+            "--log-file",
+            "trace.log",
+            "--input-script",
+            "walkthrough.txt",
+            "--max-steps",
+            "1000",
+            "--load-snapshot",
+            "snapshots/a.snap",
+            "--patch",
+            "a.patch",
+            "--patch",
+            "b.patch",
+        ])
+        .unwrap();
+
+        assert_eq!(options.command, Command::Trace);
+        assert_eq!(options.log_level, LevelFilter::Debug);
+        assert_eq!(options.log_file, PathBuf::from("trace.log"));
+        assert_eq!(options.input_script, Some(PathBuf::from("walkthrough.txt")));
+        assert_eq!(options.max_steps, Some(1000));
+        assert_eq!(
+            options.load_snapshot,
+            Some(PathBuf::from("snapshots/a.snap"))
+        );
+        assert_eq!(
+            options.patches,
+            vec![PathBuf::from("a.patch"), PathBuf::from("b.patch")]
+        );
+    }
+
+    #[test]
+    fn trace_options() {
+        let options = parse(&[
+            "trace",
+            "--trace-format",
+            "binary",
+            "--trace-range",
+            "5000-6000",
+            "--trace-opcodes",
+            "call,ret",
+        ])
+        .unwrap();
+
+        assert_eq!(options.trace_format, TraceFormat::Binary);
+        assert_eq!(options.trace_filter.addresses, Some(5000..=6000));
+        assert_eq!(
+            options.trace_filter.opcodes,
+            Some(vec![Opcode::Call, Opcode::Ret].into_iter().collect())
+        );
+        assert!(parse(&["--trace-range", "10"]).is_err());
+        assert!(parse(&["--trace-opcodes", "call,jump"]).is_err());
+    }
+
+    #[test]
+    fn explore_options() {
+        let options = parse(&["explore", "--max-rooms", "20", "--map-format", "json"]).unwrap();
+        assert_eq!(options.command, Command::Explore);
+        assert_eq!(options.max_rooms, 20);
+        assert_eq!(options.map_format, MapFormat::Json);
+        assert!(parse(&["--map-format", "svg"]).is_err());
+    }
+
+    #[test]
+    fn analysis_options() {
+        let options = parse(&["cfg", "--function", "6027", "--decrypt"]).unwrap();
+        assert_eq!(options.command, Command::Cfg);
+        assert_eq!(options.function, Some(6027));
+        assert!(options.decrypt);
+        assert!(parse(&["calls", "--function", "main"]).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--max-steps", "many"]).is_err());
+        assert!(parse(&["--log-level", "loud"]).is_err());
+        assert!(parse(&["--log-file"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["run", "a.bin", "b.bin"]).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Recognizes the challenge codes in the output of the program.
+//!
+//! Codes are runs of exactly 12 ASCII letters and digits with both upper and
+//! lower case letters, like `ggZNxmyaPzLw`, which rules out ordinary words.
+//!
+//! The last code is seen in a mirror, so codes printed after a mention of a
+//! mirror are also reported reversed, with the letters that mirror each other
+//! swapped.
+
+use crate::vm::StepEvent;
+use std::io::{self, Write};
+
+const CODE_LEN: usize = 12;
+const MAX_CONTEXT: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Code {
+    pub text: String,
+    /// Index of the step that printed the last character of the code.
+    pub step: u64,
+    /// The text printed before the code on the same line, or the previous
+    /// line when the code starts a line.
+    pub context: String,
+    /// The code as it reads outside the mirror, for codes seen in one.
+    pub mirrored: Option<String>,
+}
+
+impl Code {
+    /// The code to enter on the challenge website.
+    pub fn answer(&self) -> &str {
+        self.mirrored.as_deref().unwrap_or(&self.text)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CodeExtractor {
+    token: String,
+    line: String,
+    previous_line: String,
+    last_step: u64,
+    codes: Vec<Code>,
+}
+
+impl CodeExtractor {
+    pub fn new() -> CodeExtractor {
+        CodeExtractor::default()
+    }
+
+    pub fn observe(&mut self, event: &StepEvent) {
+        for c in event.output() {
+            self.observe_char(c, event.index);
+        }
+    }
+
+    pub fn observe_char(&mut self, c: char, step: u64) {
+        if c.is_ascii_alphanumeric() {
+            self.token.push(c);
+            self.last_step = step;
+            return;
+        }
+        self.end_token();
+        if c == '\n' {
+            if !self.line.trim().is_empty() {
+                self.previous_line = std::mem::take(&mut self.line);
+            }
+            self.line.clear();
+        } else {
+            self.line.push(c);
+        }
+    }
+
+    /// Checks the text printed last, for output that ends with a code.
+    pub fn finish(&mut self) {
+        self.end_token();
+    }
+
+    /// The codes found so far in the order they were printed, without
+    /// duplicates.
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    pub fn write_report<W: Write>(&self, mut out: W) -> io::Result<()> {
+        for code in &self.codes {
+            match &code.mirrored {
+                Some(mirrored) => write!(
+                    out,
+                    "{} (step {}, mirrored from {})",
+                    mirrored, code.step, code.text
+                )?,
+                None => write!(out, "{} (step {})", code.text, code.step)?,
+            }
+            if !code.context.is_empty() {
+                write!(out, " {}", code.context)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn end_token(&mut self) {
+        let token = std::mem::take(&mut self.token);
+        if is_code(&token) && !self.codes.iter().any(|code| code.text == token) {
+            let context = match self.line.trim() {
+                "" => self.previous_line.trim(),
+                line => line,
+            };
+            let skip = context.chars().count().saturating_sub(MAX_CONTEXT);
+            let mirrored = context.to_lowercase().contains("mirror");
+            self.codes.push(Code {
+                text: token.clone(),
+                step: self.last_step,
+                context: context.chars().skip(skip).collect(),
+                mirrored: mirrored.then(|| mirror(&token)),
+            });
+        }
+        self.line.push_str(&token);
+    }
+}
+
+fn is_code(token: &str) -> bool {
+    token.len() == CODE_LEN
+        && token.chars().any(|c| c.is_ascii_uppercase())
+        && token.chars().any(|c| c.is_ascii_lowercase())
+}
+
+/// Reads the text from the other side of a mirror.
+pub fn mirror(text: &str) -> String {
+    text.chars()
+        .rev()
+        .map(|c| match c {
+            'b' => 'd',
+            'd' => 'b',
+            'p' => 'q',
+            'q' => 'p',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(text: &str) -> Vec<Code> {
+        let mut extractor = CodeExtractor::new();
+        for (step, c) in text.chars().enumerate() {
+            extractor.observe_char(c, step as u64);
+        }
+        extractor.finish();
+        extractor.codes().to_vec()
+    }
+
+    #[test]
+    fn recognizes_codes() {
+        let codes = extract(
+            "Please record your progress by putting codes like\n\
+             this one into the challenge website: ggZNxmyaPzLw\n\n\
+             You write \"tEuuRLXSEkHh\" on the tablet.\n\
+             abcdefghijkl ABCDEFGHIJKL abcdefghijklM\n\
+             The code is:\nWXIHIdEBKRGz",
+        );
+        let summary: Vec<(&str, u64, &str)> = codes
+            .iter()
+            .map(|code| (code.text.as_str(), code.step, code.context.as_str()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                ("ggZNxmyaPzLw", 98, "this one into the challenge website:"),
+                ("tEuuRLXSEkHh", 123, "You write \""),
+                ("WXIHIdEBKRGz", 205, "The code is:"),
+            ]
+        );
+    }
+
+    #[test]
+    fn mirrored_codes() {
+        let codes = extract(
+            "Through the mirror, you see \"dbqpWYbUoUxi\" scrawled in charcoal on your forehead.",
+        );
+        assert_eq!(codes[0].text, "dbqpWYbUoUxi");
+        assert_eq!(codes[0].answer(), "ixUoUdYWqpdb");
+
+        let mut report = vec![];
+        let mut extractor = CodeExtractor::new();
+        extractor.codes = codes;
+        extractor.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "ixUoUdYWqpdb (step 40, mirrored from dbqpWYbUoUxi) Through the mirror, you see \"\n"
+        );
+        assert_eq!(extract("You see ggZNxmyaPzLw")[0].mirrored, None);
+    }
+
+    #[test]
+    fn removes_duplicates() {
+        let codes = extract("aaaaaaBBBBBB and aaaaaaBBBBBB again");
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].step, 11);
+
+        let mut report = vec![];
+        let mut extractor = CodeExtractor::new();
+        extractor.codes = codes;
+        extractor.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "aaaaaaBBBBBB (step 11)\n"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Solver for the coin puzzle of the ruins.
+//!
+//! The monument reads `_ + _ * _^2 + _^3 - _ = 399` and every coin shows its
+//! value as dots or as a shape with as many sides. The coins are found by
+//! asking the game for the inventory and looking at each coin, then every
+//! order of the coins is tried.
+
+use crate::{
+    device::Scripted,
+    vm::{VmError, VmErrorKind, VmState, VM},
+};
+use std::{error, fmt, mem};
+
+const TARGET: i64 = 399;
+
+const NUMBERS: [&str; 10] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+const SHAPES: [(&str, u16); 7] = [
+    ("triangle", 3),
+    ("square", 4),
+    ("pentagon", 5),
+    ("hexagon", 6),
+    ("heptagon", 7),
+    ("octagon", 8),
+    ("nonagon", 9),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub name: String,
+    pub value: u16,
+}
+
+#[derive(Debug)]
+pub enum CoinError {
+    /// The description of the coin shows no value.
+    Unknown {
+        coin: String,
+        description: String,
+    },
+    Vm(VmError),
+}
+
+impl fmt::Display for CoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinError::Unknown { coin, description } => {
+                write!(f, "no value found for the {}: {}", coin, description)
+            }
+            CoinError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CoinError {}
+
+impl From<VmError> for CoinError {
+    fn from(e: VmError) -> Self {
+        CoinError::Vm(e)
+    }
+}
+
+/// Reads the value from the description of a coin, like "It has two dots on
+/// one side." or "It has a triangle on one side."
+pub fn value(description: &str) -> Option<u16> {
+    let (_, rest) = description.split_once("It has ")?;
+    let (marks, _) = rest.split_once(" on one side")?;
+    match marks.split_once(' ')? {
+        ("a", shape) => SHAPES
+            .iter()
+            .find(|(name, _)| *name == shape)
+            .map(|(_, value)| *value),
+        (number, "dot") | (number, "dots") => NUMBERS
+            .iter()
+            .position(|name| *name == number)
+            .map(|value| value as u16),
+        _ => None,
+    }
+}
+
+/// The items listed after "Your inventory:" in the output of `inv`.
+pub fn inventory(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Your inventory:"))
+        .skip(1)
+        .map_while(|line| line.strip_prefix("- "))
+        .map(|item| item.trim().to_string())
+        .collect()
+}
+
+/// Asks the game for the coins in the inventory and their values. The state
+/// of the VM is restored afterwards, so the queries leave no trace.
+pub fn inspect(vm: &mut VM) -> Result<Vec<Coin>, CoinError> {
+    if vm.state() == VmState::Halted {
+        return Ok(vec![]);
+    }
+    let snapshot = vm.snapshot();
+    let steps = vm.steps();
+    let io = Scripted::new();
+    let previous = vm.replace_io(Box::new(io.clone()));
+    let watchpoints = mem::take(vm.watchpoints_mut());
+
+    let result = query(vm, &io);
+
+    vm.restore(&snapshot);
+    vm.set_steps(steps);
+    vm.replace_io(previous);
+    *vm.watchpoints_mut() = watchpoints;
+    result
+}
+
+fn query(vm: &mut VM, io: &Scripted) -> Result<Vec<Coin>, CoinError> {
+    let coins = inventory(&ask(vm, io, "inv")?)
+        .into_iter()
+        .filter(|item| item.ends_with("coin"));
+
+    let mut found = Vec::new();
+    for name in coins {
+        let description = ask(vm, io, &format!("look {}", name))?;
+        match value(&description) {
+            Some(value) => found.push(Coin { name, value }),
+            None => {
+                return Err(CoinError::Unknown {
+                    coin: name,
+                    description: description.trim().to_string(),
+                })
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Sends a command to the game and returns its answer.
+fn ask(vm: &mut VM, io: &Scripted, command: &str) -> Result<String, VmError> {
+    io.take_output();
+    io.push_line(command);
+    match vm.run() {
+        Ok(_) => {}
+        Err(e) if e.kind == VmErrorKind::InputExhausted => {}
+        Err(e) => return Err(e),
+    }
+    Ok(io.take_output())
+}
+
+/// Finds the order to place the coins in, `None` unless there are exactly
+/// five coins and one of their orders solves the equation.
+pub fn solve(coins: &[Coin]) -> Option<Vec<Coin>> {
+    if coins.len() != 5 {
+        return None;
+    }
+    let mut order: Vec<usize> = (0..coins.len()).collect();
+    permute(&mut order, 0, &|order: &[usize]| {
+        let [a, b, c, d, e] = [0, 1, 2, 3, 4].map(|slot| i64::from(coins[order[slot]].value));
+        a + b * c.pow(2) + d.pow(3) - e == TARGET
+    })
+    .then(|| order.iter().map(|&index| coins[index].clone()).collect())
+}
+
+/// Rearranges `order[start..]` until `solved` accepts it, returning whether
+/// it did.
+fn permute(order: &mut [usize], start: usize, solved: &dyn Fn(&[usize]) -> bool) -> bool {
+    if start == order.len() {
+        return solved(order);
+    }
+    for i in start..order.len() {
+        order.swap(start, i);
+        if permute(order, start + 1, solved) {
+            return true;
+        }
+        order.swap(start, i);
+    }
+    false
+}
+
+/// The commands that place the coins in the given order.
+pub fn commands(order: &[Coin]) -> Vec<String> {
+    order
+        .iter()
+        .map(|coin| format!("use {}", coin.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary, walkthrough::Walkthrough};
+    use std::fs;
+
+    fn coin(name: &str, value: u16) -> Coin {
+        Coin {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn values_and_inventory() {
+        assert_eq!(
+            value("This coin is made of a red metal.  It has two dots on one side."),
+            Some(2)
+        );
+        assert_eq!(
+            value("This coin is somewhat corroded.  It has a triangle on one side."),
+            Some(3)
+        );
+        assert_eq!(value("It has a smiley on one side."), None);
+        assert_eq!(
+            inventory("inv\n\nYour inventory:\n- tablet\n- red coin\n\nWhat do you do?"),
+            vec!["tablet".to_string(), "red coin".to_string()]
+        );
+    }
+
+    #[test]
+    fn solve_the_equation() {
+        let coins = vec![
+            coin("red coin", 2),
+            coin("corroded coin", 3),
+            coin("shiny coin", 5),
+            coin("concave coin", 7),
+            coin("blue coin", 9),
+        ];
+        assert_eq!(
+            commands(&solve(&coins).unwrap()),
+            vec![
+                "use blue coin",
+                "use red coin",
+                "use shiny coin",
+                "use concave coin",
+                "use corroded coin"
+            ]
+        );
+        assert_eq!(solve(&coins[1..]), None);
+    }
+
+    #[test]
+    fn open_the_door_in_the_ruins() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let script = fs::read_to_string(format!("{}/walkthroughs/ruins.txt", dir)).unwrap();
+        let program = binary::challenge();
+        let io = Scripted::new();
+        let mut vm = VM::boot(io.clone()).load_program(program).unwrap();
+        Walkthrough::parse(&script)
+            .unwrap()
+            .run(&mut vm, &io)
+            .unwrap();
+
+        let steps = vm.steps();
+        let coins = inspect(&mut vm).unwrap();
+        assert_eq!(coins.len(), 5);
+        assert_eq!(vm.steps(), steps);
+
+        for command in commands(&solve(&coins).unwrap()) {
+            vm.feed_input(&format!("{}\n", command));
+        }
+        assert!(vm.run().is_err());
+        assert!(io
+            .take_output()
+            .contains("As you place the last coin, you hear a click from the north door."));
+    }
+}
@@ -0,0 +1,685 @@
+use crate::{
+    coins,
+    device::IoDevice,
+    disasm,
+    history::History,
+    interrupt,
+    opcode::Opcode,
+    teleporter, vault,
+    vm::{StepEvent, VmError, VM},
+    watch::{Access, WatchTarget},
+};
+use std::{
+    collections::BTreeSet,
+    convert::TryInto,
+    io::{self, Write},
+};
+
+pub const HELP: &str = "\
+Commands:
+  break <addr>          stop before executing the instruction at <addr>
+  delete [addr]         delete the breakpoint at <addr>, or all breakpoints
+  continue              run until a breakpoint, an error or Ctrl-C
+  step [n]              execute n instructions (default 1)
+  next                  like step, but steps over calls
+  finish                run until the current subroutine returns
+  reverse-step [n]      undo the last n instructions (default 1)
+  reverse-continue      go back to the previous breakpoint
+  goto <step>           go back to the state before the given step
+  watch <r0-r7|addr> [read|write|change|access] [== value]
+                        stop when a register or a memory cell is accessed, registers
+                        default to change, memory to write
+  unwatch <id>          delete a watchpoint
+  ignore <id> <n>       let the next n hits of a watchpoint through
+  watches               list the watchpoints with their hit counts
+  regs                  print the registers
+  stack                 print the stack, top first
+  mem <addr> <len>      print <len> words of memory from <addr>
+  set <reg> <value>     set a register, e.g. set r7 1, clears the history
+  poke <addr> <value>   overwrite a word of memory, clears the history
+  disas [addr] [n]      disassemble n instructions from addr (default: pointer)
+  teleporter            set r7 so the teleporter works and skip its confirmation
+  coins [inject]        solve the coin puzzle of the ruins, inject queues the moves
+                        as input for the game
+  vault [inject]        solve the orb puzzle in front of the vault, inject queues
+                        the moves as input for the game
+  help                  print this help
+  quit                  exit the debugger";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugCommand {
+    Break(usize),
+    Delete(Option<usize>),
+    Continue,
+    Step(u64),
+    Next,
+    Finish,
+    ReverseStep(u64),
+    ReverseContinue,
+    Goto(u64),
+    Watch(WatchTarget, Access, Option<usize>),
+    Unwatch(usize),
+    Ignore(usize, u64),
+    Watches,
+    Regs,
+    Stack,
+    Mem(usize, usize),
+    Set(usize, usize),
+    Poke(usize, u16),
+    Disas(Option<usize>, usize),
+    Teleporter,
+    Coins(bool),
+    Vault(bool),
+    Help,
+    Quit,
+}
+
+impl DebugCommand {
+    pub fn parse(line: &str) -> Result<DebugCommand, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["break", addr] | ["b", addr] => DebugCommand::Break(number(addr)?),
+            ["delete"] => DebugCommand::Delete(None),
+            ["delete", addr] => DebugCommand::Delete(Some(number(addr)?)),
+            ["continue"] | ["c"] => DebugCommand::Continue,
+            ["step"] | ["s"] => DebugCommand::Step(1),
+            ["step", n] | ["s", n] => match number(n)? {
+                0 => return Err("the step count must be at least 1".to_string()),
+                n => DebugCommand::Step(n as u64),
+            },
+            ["next"] | ["n"] => DebugCommand::Next,
+            ["finish"] => DebugCommand::Finish,
+            ["reverse-step"] | ["rs"] => DebugCommand::ReverseStep(1),
+            ["reverse-step", n] | ["rs", n] => DebugCommand::ReverseStep(number(n)? as u64),
+            ["reverse-continue"] | ["rc"] => DebugCommand::ReverseContinue,
+            ["goto", step] => DebugCommand::Goto(number(step)? as u64),
+            ["watch", target, rest @ ..] => watch(target, rest)?,
+            ["unwatch", id] => DebugCommand::Unwatch(number(id)?),
+            ["ignore", id, n] => DebugCommand::Ignore(number(id)?, number(n)? as u64),
+            ["watches"] => DebugCommand::Watches,
+            ["regs"] => DebugCommand::Regs,
+            ["stack"] => DebugCommand::Stack,
+            ["mem", addr, len] => DebugCommand::Mem(number(addr)?, number(len)?),
+            ["set", register, value] => match number(value)? {
+                value if value < 32768 => DebugCommand::Set(register_index(register)?, value),
+                _ => return Err(format!("value out of range: {}", value)),
+            },
+            ["poke", addr, value] => DebugCommand::Poke(
+                number(addr)?,
+                number(value)?
+                    .try_into()
+                    .map_err(|_| format!("value out of range: {}", value))?,
+            ),
+            ["disas"] => DebugCommand::Disas(None, 10),
+            ["disas", addr] => DebugCommand::Disas(Some(number(addr)?), 10),
+            ["disas", addr, n] => DebugCommand::Disas(Some(number(addr)?), number(n)?),
+            ["teleporter"] => DebugCommand::Teleporter,
+            ["coins"] => DebugCommand::Coins(false),
+            ["coins", "inject"] => DebugCommand::Coins(true),
+            ["vault"] => DebugCommand::Vault(false),
+            ["vault", "inject"] => DebugCommand::Vault(true),
+            ["help"] | ["h"] => DebugCommand::Help,
+            ["quit"] | ["q"] => DebugCommand::Quit,
+            _ => return Err(format!("unknown command: {}", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+/// How far to run the VM when resuming execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Steps(u64),
+    /// Until the pointer returns to the address with the given stack depth.
+    Return(usize, usize),
+    /// Until a `ret` leaves the stack shallower than the given depth.
+    Finish(usize),
+}
+
+/// Wraps a VM with breakpoints, an execution history and a command prompt.
+#[derive(Debug)]
+pub struct Debugger {
+    vm: VM,
+    breakpoints: BTreeSet<usize>,
+    history: History,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            history: History::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Reads commands from the console until `quit` or the end of input. The
+    /// program is started with `continue`, Ctrl-C stops it and returns to the
+    /// prompt.
+    pub fn repl<W: Write>(&mut self, console: &mut dyn IoDevice, out: &mut W) -> io::Result<()> {
+        interrupt::install();
+        writeln!(out, "Type 'help' for the list of commands.")?;
+
+        loop {
+            write!(out, "(debug {:05}) ", self.vm.pointer())?;
+            out.flush()?;
+            let line = match console.read_line()? {
+                Some(line) => line,
+                None => return Ok(()),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match DebugCommand::parse(&line) {
+                Ok(command) => {
+                    if !self.execute(command, out)? {
+                        return Ok(());
+                    }
+                }
+                Err(message) => writeln!(out, "{}", message)?,
+            }
+        }
+    }
+
+    /// Executes a single command, returns false when the debugger should exit.
+    pub fn execute<W: Write>(&mut self, command: DebugCommand, out: &mut W) -> io::Result<bool> {
+        match command {
+            DebugCommand::Break(addr) => {
+                self.breakpoints.insert(addr);
+                writeln!(out, "breakpoint at {:05}", addr)?;
+            }
+            DebugCommand::Delete(Some(addr)) => {
+                if !self.breakpoints.remove(&addr) {
+                    writeln!(out, "no breakpoint at {:05}", addr)?;
+                }
+            }
+            DebugCommand::Delete(None) => self.breakpoints.clear(),
+            DebugCommand::Continue => self.resume(Resume::Continue, out)?,
+            DebugCommand::Step(n) => self.resume(Resume::Steps(n), out)?,
+            DebugCommand::Next => {
+                let pointer = self.vm.pointer();
+                match self.vm.decode(pointer) {
+                    Ok(instruction) if instruction.opcode == Opcode::Call => {
+                        let depth = self.vm.stack().len();
+                        self.resume(Resume::Return(pointer + Opcode::Call.width(), depth), out)?
+                    }
+                    _ => self.resume(Resume::Steps(1), out)?,
+                }
+            }
+            DebugCommand::Finish => {
+                let depth = self.vm.stack().len();
+                self.resume(Resume::Finish(depth), out)?
+            }
+            DebugCommand::ReverseStep(n) => {
+                let step = self.vm.steps().saturating_sub(n);
+                let oldest = self.history.oldest().unwrap_or_else(|| self.vm.steps());
+                self.rewind(step.max(oldest), out)?
+            }
+            DebugCommand::ReverseContinue => {
+                let breakpoints = &self.breakpoints;
+                match self
+                    .history
+                    .rfind(&self.vm, |pointer| breakpoints.contains(&pointer))
+                {
+                    Some(step) => self.rewind(step, out)?,
+                    None => {
+                        let oldest = self.history.oldest().unwrap_or_else(|| self.vm.steps());
+                        writeln!(out, "reached the start of the history")?;
+                        self.rewind(oldest, out)?
+                    }
+                }
+            }
+            DebugCommand::Goto(step) => self.rewind(step, out)?,
+            DebugCommand::Watch(target, access, condition) => {
+                let id = self.vm.watchpoints_mut().add(target, access, condition);
+                writeln!(out, "watchpoint {}: {} {}", id, target, access)?;
+            }
+            DebugCommand::Unwatch(id) => {
+                if !self.vm.watchpoints_mut().remove(id) {
+                    writeln!(out, "no watchpoint {}", id)?;
+                }
+            }
+            DebugCommand::Ignore(id, n) => {
+                if !self.vm.watchpoints_mut().set_ignore_count(id, n) {
+                    writeln!(out, "no watchpoint {}", id)?;
+                }
+            }
+            DebugCommand::Watches => {
+                for watchpoint in self.vm.watchpoints().list() {
+                    writeln!(out, "{}", watchpoint)?;
+                }
+            }
+            DebugCommand::Regs => self.print_registers(out)?,
+            DebugCommand::Stack => {
+                for (depth, value) in self.vm.stack().iter().rev().enumerate() {
+                    writeln!(out, "{:>4}: {}", depth, value)?;
+                }
+            }
+            DebugCommand::Mem(addr, len) => {
+                let memory = self.vm.memory();
+                let end = addr.saturating_add(len).min(memory.len());
+                for start in (addr.min(end)..end).step_by(8) {
+                    let words: Vec<String> = memory[start..(start + 8).min(end)]
+                        .iter()
+                        .map(|word| format!("{:>5}", word))
+                        .collect();
+                    writeln!(out, "{:05}: {}", start, words.join(" "))?;
+                }
+            }
+            DebugCommand::Set(register, value) => match self.vm.poke_register(register, value) {
+                Ok(()) => self.history.clear(),
+                Err(error) => writeln!(out, "{}", error.kind)?,
+            },
+            DebugCommand::Poke(addr, value) => match self.vm.poke(addr, value) {
+                Ok(()) => self.history.clear(),
+                Err(error) => writeln!(out, "{}", error.kind)?,
+            },
+            DebugCommand::Disas(addr, n) => {
+                let addr = addr.unwrap_or_else(|| self.vm.pointer());
+                self.print_disassembly(addr, n, out)?;
+            }
+            DebugCommand::Teleporter => self.teleporter(out)?,
+            DebugCommand::Coins(inject) => self.coins(inject, out)?,
+            DebugCommand::Vault(inject) => {
+                let grid = vault::Grid::parse(vault::CHALLENGE).expect("the grid is valid");
+                match grid.solve() {
+                    Some(path) => self.print_moves(&vault::commands(&path), inject, out)?,
+                    None => writeln!(out, "the orb cannot reach the vault door")?,
+                }
+            }
+            DebugCommand::Help => writeln!(out, "{}", HELP)?,
+            DebugCommand::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn resume<W: Write>(&mut self, resume: Resume, out: &mut W) -> io::Result<()> {
+        interrupt::take();
+        let mut executed = 0;
+
+        loop {
+            if executed > 0 && self.breakpoints.contains(&self.vm.pointer()) {
+                writeln!(out, "breakpoint at {:05}", self.vm.pointer())?;
+                break;
+            }
+            if interrupt::take() {
+                writeln!(out, "\ninterrupted at {:05}", self.vm.pointer())?;
+                break;
+            }
+
+            let event = match self.history.step(&mut self.vm) {
+                Ok(event) => event,
+                Err(error) => {
+                    self.print_error(&error, out)?;
+                    break;
+                }
+            };
+            executed += 1;
+
+            if event.exit.is_some() {
+                writeln!(out, "program halted at {:05}", event.pointer)?;
+                break;
+            }
+            if !event.watch_hits.is_empty() {
+                for hit in &event.watch_hits {
+                    writeln!(out, "{}", hit)?;
+                }
+                break;
+            }
+            if self.finished(resume, executed, &event) {
+                break;
+            }
+        }
+
+        self.print_disassembly(self.vm.pointer(), 1, out)
+    }
+
+    fn teleporter<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let confirmation = match teleporter::find(self.vm.memory()) {
+            Some(confirmation) => confirmation,
+            None => return writeln!(out, "no teleporter confirmation found"),
+        };
+        let value = match teleporter::solve(&confirmation, 1..32768).first() {
+            Some(&value) => value,
+            None => return writeln!(out, "no value passes the confirmation"),
+        };
+        match teleporter::patch(&mut self.vm, &confirmation, value) {
+            Ok(()) => {
+                self.history.clear();
+                writeln!(
+                    out,
+                    "r{}={}, confirmation at {:05} skipped",
+                    confirmation.register, value, confirmation.routine
+                )
+            }
+            Err(error) => writeln!(out, "{}", error.kind),
+        }
+    }
+
+    fn coins<W: Write>(&mut self, inject: bool, out: &mut W) -> io::Result<()> {
+        let found = match coins::inspect(&mut self.vm) {
+            Ok(found) => found,
+            Err(error) => return writeln!(out, "{}", error),
+        };
+        let order = match coins::solve(&found) {
+            Some(order) => order,
+            None => return writeln!(out, "no solution with {} coins", found.len()),
+        };
+        self.print_moves(&coins::commands(&order), inject, out)
+    }
+
+    /// Prints the commands for the game, queueing them as its input too when
+    /// `inject` is set.
+    fn print_moves<W: Write>(
+        &mut self,
+        commands: &[String],
+        inject: bool,
+        out: &mut W,
+    ) -> io::Result<()> {
+        for command in commands {
+            writeln!(out, "{}", command)?;
+            if inject {
+                self.vm.feed_input(&format!("{}\n", command));
+            }
+        }
+        Ok(())
+    }
+
+    fn rewind<W: Write>(&mut self, step: u64, out: &mut W) -> io::Result<()> {
+        match self.history.goto(&mut self.vm, step) {
+            Ok(()) => writeln!(out, "at step {}", self.vm.steps())?,
+            Err(error) => writeln!(out, "{}", error)?,
+        }
+        self.print_disassembly(self.vm.pointer(), 1, out)
+    }
+
+    fn finished(&self, resume: Resume, executed: u64, event: &StepEvent) -> bool {
+        match resume {
+            Resume::Continue => false,
+            Resume::Steps(n) => executed >= n,
+            Resume::Return(addr, depth) => {
+                self.vm.pointer() == addr && self.vm.stack().len() == depth
+            }
+            Resume::Finish(depth) => {
+                event.instruction.opcode == Opcode::Ret && self.vm.stack().len() < depth
+            }
+        }
+    }
+
+    fn print_error<W: Write>(&self, error: &VmError, out: &mut W) -> io::Result<()> {
+        writeln!(out, "\nerror: {}", error)
+    }
+
+    fn print_registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (index, value) in self.vm.registers().iter().enumerate() {
+            write!(out, "r{}={} ", index, value)?;
+        }
+        writeln!(
+            out,
+            "pointer={} steps={}",
+            self.vm.pointer(),
+            self.vm.steps()
+        )
+    }
+
+    fn print_disassembly<W: Write>(&self, addr: usize, n: usize, out: &mut W) -> io::Result<()> {
+        let memory = self.vm.memory();
+        let mut addr = addr;
+        for _ in 0..n {
+            if addr >= memory.len() {
+                break;
+            }
+            let line = disasm::line_at(memory, addr);
+            let marker = if addr == self.vm.pointer() {
+                "=>"
+            } else {
+                "  "
+            };
+            let breakpoint = if self.breakpoints.contains(&addr) {
+                "*"
+            } else {
+                " "
+            };
+            writeln!(out, "{}{} {}", marker, breakpoint, line)?;
+            addr += line.width();
+        }
+        Ok(())
+    }
+}
+
+fn number(word: &str) -> Result<usize, String> {
+    let result = match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    result.map_err(|_| format!("invalid number: {}", word))
+}
+
+fn watch(target: &str, rest: &[&str]) -> Result<DebugCommand, String> {
+    let target = match register_index(target) {
+        Ok(register) => WatchTarget::Register(register),
+        Err(_) => WatchTarget::Memory(number(target)?),
+    };
+    let (access, rest) = match rest {
+        ["read", rest @ ..] => (Access::Read, rest),
+        ["write", rest @ ..] => (Access::Write, rest),
+        ["change", rest @ ..] => (Access::Change, rest),
+        ["access", rest @ ..] => (Access::Any, rest),
+        _ => match target {
+            WatchTarget::Register(_) => (Access::Change, rest),
+            WatchTarget::Memory(_) => (Access::Write, rest),
+        },
+    };
+    let condition = match rest {
+        [] => None,
+        ["==", value] => Some(number(value)?),
+        _ => return Err(format!("invalid condition: {}", rest.join(" "))),
+    };
+    Ok(DebugCommand::Watch(target, access, condition))
+}
+
+fn register_index(word: &str) -> Result<usize, String> {
+    match word.strip_prefix('r').map(str::parse) {
+        Some(Ok(register)) if register < 8 => Ok(register),
+        _ => Err(format!("invalid register: {}", word)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Scripted;
+
+    const R0: u16 = 32768;
+
+    fn boot(program: &[u16]) -> Debugger {
+        Debugger::new(
+            VM::boot(Scripted::new())
+                .load_program(program.to_vec())
+                .unwrap(),
+        )
+    }
+
+    fn execute(debugger: &mut Debugger, line: &str) -> String {
+        let mut out = vec![];
+        debugger
+            .execute(DebugCommand::parse(line).unwrap(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            DebugCommand::parse("break 0x10"),
+            Ok(DebugCommand::Break(16))
+        );
+        assert_eq!(DebugCommand::parse("step"), Ok(DebugCommand::Step(1)));
+        assert_eq!(
+            DebugCommand::parse("set r7 25"),
+            Ok(DebugCommand::Set(7, 25))
+        );
+        assert_eq!(
+            DebugCommand::parse("disas 100"),
+            Ok(DebugCommand::Disas(Some(100), 10))
+        );
+        assert_eq!(
+            DebugCommand::parse("watch r7 read"),
+            Ok(DebugCommand::Watch(
+                WatchTarget::Register(7),
+                Access::Read,
+                None
+            ))
+        );
+        assert_eq!(
+            DebugCommand::parse("watch 100 == 5"),
+            Ok(DebugCommand::Watch(
+                WatchTarget::Memory(100),
+                Access::Write,
+                Some(5)
+            ))
+        );
+        assert_eq!(
+            DebugCommand::parse("coins inject"),
+            Ok(DebugCommand::Coins(true))
+        );
+        assert!(DebugCommand::parse("watch r7 read > 5").is_err());
+        assert!(DebugCommand::parse("set r8 1").is_err());
+        assert!(DebugCommand::parse("set r0 32768").is_err());
+        assert!(DebugCommand::parse("poke 1 65536").is_err());
+        assert!(DebugCommand::parse("fly").is_err());
+        assert!(DebugCommand::parse("step 0").is_err());
+    }
+
+    #[test]
+    fn breakpoints() {
+        // 0: add r0 r0 1; 4: jmp 0
+        let mut debugger = boot(&[9, R0, R0, 1, 6, 0]);
+        execute(&mut debugger, "break 4");
+
+        let out = execute(&mut debugger, "continue");
+        assert!(out.starts_with("breakpoint at 00004"));
+        assert_eq!(debugger.vm().registers()[0], 1);
+
+        execute(&mut debugger, "continue");
+        assert_eq!(debugger.vm().registers()[0], 2);
+
+        execute(&mut debugger, "delete 4");
+        execute(&mut debugger, "step 10");
+        assert_eq!(debugger.vm().steps(), 13);
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        // 0: call 5; 2: noop; 3: halt; 4: noop; 5: set r0 7; 8: ret
+        let mut debugger = boot(&[17, 5, 21, 0, 21, 1, R0, 7, 18]);
+
+        execute(&mut debugger, "next");
+        assert_eq!(debugger.vm().pointer(), 2);
+        assert_eq!(debugger.vm().registers()[0], 7);
+    }
+
+    #[test]
+    fn finish_runs_until_return() {
+        let mut debugger = boot(&[17, 5, 21, 0, 21, 1, R0, 7, 18]);
+
+        execute(&mut debugger, "step");
+        assert_eq!(debugger.vm().pointer(), 5);
+        execute(&mut debugger, "finish");
+        assert_eq!(debugger.vm().pointer(), 2);
+    }
+
+    #[test]
+    fn watchpoints() {
+        // 0: add r0 r0 1; 4: jmp 0
+        let mut debugger = boot(&[9, R0, R0, 1, 6, 0]);
+        execute(&mut debugger, "watch r0 == 3");
+
+        let out = execute(&mut debugger, "continue");
+        assert!(out.starts_with("watchpoint 1: r0 written 2 -> 3 at 00000"));
+        assert_eq!(debugger.vm().registers()[0], 3);
+        assert!(execute(&mut debugger, "watches").starts_with("1: r0 change == 3 (hits: 1)"));
+    }
+
+    #[test]
+    fn reverse_execution() {
+        // 0: add r0 r0 1; 4: jmp 0
+        let mut debugger = boot(&[9, R0, R0, 1, 6, 0]);
+        execute(&mut debugger, "step 10");
+
+        assert_eq!(
+            execute(&mut debugger, "reverse-step 3"),
+            "at step 7\n=>  00004: jmp 0\n"
+        );
+        assert_eq!(debugger.vm().registers()[0], 4);
+
+        execute(&mut debugger, "break 4");
+        assert_eq!(
+            execute(&mut debugger, "rc"),
+            "at step 5\n=>* 00004: jmp 0\n"
+        );
+        assert_eq!(debugger.vm().registers()[0], 3);
+
+        execute(&mut debugger, "goto 0");
+        assert_eq!(debugger.vm().registers()[0], 0);
+        assert!(execute(&mut debugger, "goto 1").starts_with("step 1 is ahead"));
+        assert_eq!(
+            execute(&mut debugger, "rc"),
+            "reached the start of the history\nat step 0\n=>  00000: add r0 r0 1\n"
+        );
+    }
+
+    #[test]
+    fn stops_on_errors_and_halt() {
+        let mut debugger = boot(&[3, R0]);
+        assert!(execute(&mut debugger, "continue").contains("error: pop from an empty stack"));
+
+        let mut debugger = boot(&[21, 0]);
+        assert!(execute(&mut debugger, "continue").starts_with("program halted at 00001"));
+    }
+
+    #[test]
+    fn inspect_and_modify() {
+        let mut debugger = boot(&[19, R0, 0]);
+        execute(&mut debugger, "set r0 65");
+        execute(&mut debugger, "poke 2 21");
+
+        assert!(execute(&mut debugger, "regs").starts_with("r0=65 r1=0"));
+        assert_eq!(
+            execute(&mut debugger, "mem 0 3"),
+            "00000:    19 32768    21\n"
+        );
+        assert!(execute(&mut debugger, &format!("mem 5 {}", usize::MAX)).starts_with("00005:"));
+        assert_eq!(
+            execute(&mut debugger, "disas 0 2"),
+            "=>  00000: out r0\n    00002: noop\n"
+        );
+    }
+
+    #[test]
+    fn inject_puzzle_moves() {
+        // echoes every line: 0: in r0; 2: out r0; 4: jmp 0
+        let mut debugger = boot(&[20, R0, 19, R0, 6, 0]);
+        let moves = execute(&mut debugger, "vault inject");
+        assert!(moves.starts_with("go north\ngo east\n"));
+        assert_eq!(moves.lines().count(), 12);
+        assert_eq!(debugger.vm.input_buffer().last(), Some(&('g' as usize)));
+
+        execute(&mut debugger, "step 4");
+        assert_eq!(debugger.vm.registers()[0], 'o' as usize);
+        assert_eq!(
+            execute(&mut debugger, "coins"),
+            "no solution with 0 coins\n"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+/// The terminal the VM reads characters from and writes characters to.
+pub trait IoDevice: fmt::Debug {
+    /// Reads the next line of input, including the trailing newline. Returns
+    /// `None` when there is no more input.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+
+    fn write_char(&mut self, c: char) -> io::Result<()>;
+}
+
+/// Reads from stdin and writes to stdout.
+#[derive(Debug, Default)]
+pub struct Terminal {
+    script: VecDeque<String>,
+}
+
+impl Terminal {
+    pub fn new() -> Terminal {
+        Terminal::default()
+    }
+
+    /// Reads the given lines before reading stdin, echoing them as if they
+    /// were typed in.
+    pub fn with_script<I, S>(lines: I) -> Terminal
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Terminal {
+            script: lines.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl IoDevice for Terminal {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        if let Some(mut line) = self.script.pop_front() {
+            if !line.ends_with('\n') {
+                line.push('\n');
+            }
+            print!("{}", line);
+            return Ok(Some(line));
+        }
+        io::stdout().flush()?;
+
+        let mut buffer = String::new();
+        if io::stdin().lock().read_line(&mut buffer)? == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(buffer))
+        }
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        print!("{}", c);
+        Ok(())
+    }
+}
+
+/// Feeds input from an in-memory queue of lines and captures the output.
+///
+/// Clones share the same queue and buffer, so a clone kept outside of the VM
+/// can be used to push more input and inspect the output between runs.
+#[derive(Debug, Default, Clone)]
+pub struct Scripted {
+    state: Rc<RefCell<ScriptedState>>,
+}
+
+#[derive(Debug, Default)]
+struct ScriptedState {
+    input: VecDeque<String>,
+    output: String,
+}
+
+impl Scripted {
+    pub fn new() -> Scripted {
+        Scripted::default()
+    }
+
+    pub fn with_input<I, S>(lines: I) -> Scripted
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let scripted = Scripted::new();
+        for line in lines {
+            scripted.push_line(line);
+        }
+        scripted
+    }
+
+    /// Queues a line of input, a newline is appended when it is missing.
+    pub fn push_line<S: Into<String>>(&self, line: S) {
+        let mut line = line.into();
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        self.state.borrow_mut().input.push_back(line);
+    }
+
+    /// Number of lines queued but not read yet.
+    pub fn pending_lines(&self) -> usize {
+        self.state.borrow().input.len()
+    }
+
+    /// The output captured so far.
+    pub fn output(&self) -> String {
+        self.state.borrow().output.clone()
+    }
+
+    /// Returns the output captured so far and clears the buffer.
+    pub fn take_output(&self) -> String {
+        std::mem::take(&mut self.state.borrow_mut().output)
+    }
+}
+
+impl IoDevice for Scripted {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.state.borrow_mut().input.pop_front())
+    }
+
+    fn write_char(&mut self, c: char) -> io::Result<()> {
+        self.state.borrow_mut().output.push(c);
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    opcode::Opcode,
+    vm::{Instruction, Value},
+};
+use std::fmt;
+
+/// A disassembled line, starting at `addr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub item: Item,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Instruction(Instruction),
+    /// A run of `out` instructions with printable literal operands.
+    Text(String),
+    /// A word that cannot be decoded as an instruction.
+    Word(u16),
+}
+
+impl Line {
+    /// Number of words covered by the line.
+    pub fn width(&self) -> usize {
+        match &self.item {
+            Item::Instruction(instruction) => instruction.opcode.width(),
+            Item::Text(text) => text.chars().count() * Opcode::Out.width(),
+            Item::Word(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:05}: {}", self.addr, self.item)
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Instruction(instruction) => write!(f, "{}", instruction),
+            Item::Text(text) => write!(f, "{} {}", Opcode::Out, quote(text)),
+            Item::Word(word) => write!(f, ".word {}", word),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode)?;
+        for operand in &self.operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles the words from `start` to the end of `memory`.
+pub fn disassemble(memory: &[u16], start: usize) -> Vec<Line> {
+    let mut lines: Vec<Line> = Vec::new();
+    let mut addr = start;
+
+    while addr < memory.len() {
+        let line = match decode(memory, addr) {
+            Some(instruction) => match printable_char(&instruction) {
+                Some(c) => {
+                    if let Some(Line {
+                        item: Item::Text(text),
+                        ..
+                    }) = lines.last_mut()
+                    {
+                        text.push(c);
+                        addr += instruction.opcode.width();
+                        continue;
+                    }
+                    Line {
+                        addr,
+                        item: Item::Text(c.to_string()),
+                    }
+                }
+                None => Line {
+                    addr,
+                    item: Item::Instruction(instruction),
+                },
+            },
+            None => Line {
+                addr,
+                item: Item::Word(memory[addr]),
+            },
+        };
+        addr += line.width();
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Disassembles a single instruction, without collapsing `out` runs.
+pub fn line_at(memory: &[u16], addr: usize) -> Line {
+    let item = match decode(memory, addr) {
+        Some(instruction) => Item::Instruction(instruction),
+        None => Item::Word(memory[addr]),
+    };
+    Line { addr, item }
+}
+
+/// Decodes the instruction at `addr`, returns `None` for data.
+pub fn decode(memory: &[u16], addr: usize) -> Option<Instruction> {
+    let opcode = Opcode::from_code(memory[addr].into())?;
+    let operands = (1..opcode.width())
+        .map(|offset| {
+            memory
+                .get(addr + offset)
+                .and_then(|word| Value::decode((*word).into()))
+        })
+        .collect::<Option<_>>()?;
+
+    Some(Instruction { opcode, operands })
+}
+
+fn printable_char(instruction: &Instruction) -> Option<char> {
+    match (instruction.opcode, instruction.operands.as_slice()) {
+        (Opcode::Out, [Value::Number(code)]) => {
+            let c = char::from(*code as u8);
+            if *code < 128 && (c == '\n' || c.is_ascii_graphic() || c == ' ') {
+                Some(c)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\x{:02x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(memory: &[u16]) -> Vec<String> {
+        disassemble(memory, 0)
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn instructions() {
+        assert_eq!(
+            render(&[9, 32768, 32769, 4, 19, 32768, 18, 0]),
+            vec![
+                "00000: add r0 r1 4",
+                "00004: out r0",
+                "00006: ret",
+                "00007: halt"
+            ]
+        );
+    }
+
+    #[test]
+    fn collapses_out_runs() {
+        assert_eq!(
+            render(&[19, 72, 19, 34, 19, 10, 21, 19, 92, 19, 7]),
+            vec![
+                "00000: out \"H\\\"\\n\"",
+                "00006: noop",
+                "00007: out \"\\\\\"",
+                "00009: out 7"
+            ]
+        );
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(
+            render(&[65535, 1, 32776, 0, 9, 32768]),
+            vec![
+                "00000: .word 65535",
+                "00001: .word 1",
+                "00002: .word 32776",
+                "00003: halt",
+                "00004: .word 9",
+                "00005: .word 32768"
+            ]
+        );
+    }
+}
@@ -0,0 +1,410 @@
+//! Automatic exploration of the rooms of the text adventure.
+//!
+//! The explorer reads the room the program describes, like
+//!
+//! ```text
+//! == Foothills ==
+//! You find yourself standing at the base of an enormous mountain.
+//!
+//! Things of interest here:
+//! - tablet
+//!
+//! There are 2 exits:
+//! - doorway
+//! - south
+//!
+//! What do you do?
+//! ```
+//!
+//! then takes a snapshot and tries every exit with `go <exit>`, restoring the
+//! snapshot after each move. Rooms are told apart by everything they print,
+//! so rooms that look exactly the same, as in a maze, are merged into one.
+
+use crate::{
+    device::Scripted,
+    json,
+    snapshot::Snapshot,
+    vm::{ExitReason, VmError, VmErrorKind, VM},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    error, fmt,
+    io::{self, Write},
+};
+
+/// Instructions a single move may take before it is given up.
+const MAX_MOVE_STEPS: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Room {
+    pub name: String,
+    pub description: String,
+    pub items: Vec<String>,
+    pub exits: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Dot,
+    Json,
+}
+
+/// Where taking an exit leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Room(usize),
+    /// The program halted, with the last line it printed.
+    Halted(String),
+    /// The program printed no room or took too long.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Passage {
+    pub from: usize,
+    pub exit: String,
+    pub to: Destination,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Map {
+    pub rooms: Vec<Room>,
+    pub passages: Vec<Passage>,
+}
+
+#[derive(Debug)]
+pub enum ExploreError {
+    /// The program printed no room to start from, not even for `look`.
+    NoRoom,
+    Vm(VmError),
+}
+
+impl fmt::Display for ExploreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExploreError::NoRoom => write!(f, "the program did not describe a room"),
+            ExploreError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ExploreError {}
+
+impl From<VmError> for ExploreError {
+    fn from(e: VmError) -> Self {
+        ExploreError::Vm(e)
+    }
+}
+
+impl Room {
+    /// Parses the last room described in the output.
+    pub fn parse(output: &str) -> Option<Room> {
+        let lines: Vec<&str> = output.lines().collect();
+        let start = lines.iter().rposition(|line| header(line).is_some())?;
+        let mut room = Room {
+            name: header(lines[start])?.to_string(),
+            description: String::new(),
+            items: vec![],
+            exits: vec![],
+        };
+
+        let mut description = vec![];
+        let mut section = Section::Description;
+        for line in &lines[start + 1..] {
+            if line.starts_with("Things of interest here:") {
+                section = Section::Items;
+            } else if line.starts_with("There are ") || line.starts_with("There is ") {
+                section = Section::Exits;
+            } else if let Some(entry) = line.strip_prefix("- ") {
+                match section {
+                    Section::Items => room.items.push(entry.trim().to_string()),
+                    Section::Exits => room.exits.push(entry.trim().to_string()),
+                    _ => {}
+                }
+            } else if line.trim().is_empty() || line.starts_with("What do you do?") {
+                if section != Section::Description || !description.is_empty() {
+                    section = Section::Other;
+                }
+            } else if section == Section::Description {
+                description.push(line.trim());
+            }
+        }
+        room.description = description.join("\n");
+        Some(room)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Description,
+    Items,
+    Exits,
+    Other,
+}
+
+fn header(line: &str) -> Option<&str> {
+    line.strip_prefix("== ")?.strip_suffix(" ==")
+}
+
+/// Explores the rooms reachable from the room the program is in, visiting up
+/// to `max_rooms` rooms breadth first. The VM reads from and writes to `io`
+/// and is left in the state of the last move.
+pub fn explore(vm: &mut VM, io: &Scripted, max_rooms: usize) -> Result<Map, ExploreError> {
+    let mut map = Map::default();
+    let mut ids: HashMap<Room, usize> = HashMap::new();
+    let mut queue: VecDeque<(usize, Snapshot)> = VecDeque::new();
+
+    let room = match run_until_input(vm, io)? {
+        Outcome::Room(room) => room,
+        Outcome::Halted(_) => return Err(ExploreError::NoRoom),
+        Outcome::Nothing => {
+            io.push_line("look");
+            match run_until_input(vm, io)? {
+                Outcome::Room(room) => room,
+                _ => return Err(ExploreError::NoRoom),
+            }
+        }
+    };
+    ids.insert(room.clone(), 0);
+    map.rooms.push(room);
+    queue.push_back((0, vm.snapshot()));
+
+    while let Some((from, snapshot)) = queue.pop_front() {
+        for exit in map.rooms[from].exits.clone() {
+            vm.restore(&snapshot);
+            io.take_output();
+            io.push_line(format!("go {}", exit));
+
+            let to = match run_until_input(vm, io)? {
+                Outcome::Room(room) => match ids.get(&room) {
+                    Some(id) => Destination::Room(*id),
+                    None if map.rooms.len() < max_rooms => {
+                        let id = map.rooms.len();
+                        ids.insert(room.clone(), id);
+                        map.rooms.push(room);
+                        queue.push_back((id, vm.snapshot()));
+                        Destination::Room(id)
+                    }
+                    None => Destination::Unknown,
+                },
+                Outcome::Halted(message) => Destination::Halted(message),
+                Outcome::Nothing => Destination::Unknown,
+            };
+            map.passages.push(Passage { from, exit, to });
+        }
+    }
+    Ok(map)
+}
+
+enum Outcome {
+    Room(Room),
+    Halted(String),
+    Nothing,
+}
+
+/// Runs the program until it waits for input, halts or runs out of steps,
+/// returning the room it described or the last line it printed when it
+/// halted.
+fn run_until_input(vm: &mut VM, io: &Scripted) -> Result<Outcome, VmError> {
+    let halted = match vm.run_for(MAX_MOVE_STEPS) {
+        Ok(ExitReason::Halted) => true,
+        Ok(ExitReason::StepLimit) => return Ok(Outcome::Nothing),
+        Err(e) if e.kind == VmErrorKind::InputExhausted => false,
+        Err(e) => return Err(e),
+    };
+    let output = io.take_output();
+    if halted {
+        let last = output.lines().rev().find(|line| !line.trim().is_empty());
+        return Ok(Outcome::Halted(last.unwrap_or_default().trim().to_string()));
+    }
+    Ok(Room::parse(&output).map_or(Outcome::Nothing, Outcome::Room))
+}
+
+impl Map {
+    /// Writes the map as a Graphviz digraph, halting moves lead to box
+    /// shaped nodes with the last line of the output.
+    pub fn write_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "digraph map {{")?;
+        for (id, room) in self.rooms.iter().enumerate() {
+            let mut label = room.name.clone();
+            if !room.items.is_empty() {
+                label.push_str(&format!("\n({})", room.items.join(", ")));
+            }
+            writeln!(out, "    room{} [label={}];", id, dot_string(&label))?;
+        }
+        for (index, passage) in self.passages.iter().enumerate() {
+            let to = match &passage.to {
+                Destination::Room(id) => format!("room{}", id),
+                Destination::Halted(message) => {
+                    writeln!(
+                        out,
+                        "    end{} [shape=box, label={}];",
+                        index,
+                        dot_string(message)
+                    )?;
+                    format!("end{}", index)
+                }
+                Destination::Unknown => {
+                    writeln!(out, "    end{} [shape=point];", index)?;
+                    format!("end{}", index)
+                }
+            };
+            writeln!(
+                out,
+                "    room{} -> {} [label={}];",
+                passage.from,
+                to,
+                dot_string(&passage.exit)
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Writes the map as a JSON object with the rooms and their exits. An
+    /// exit has the id of the room it leads to, the message printed when the
+    /// program halted, or neither.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{{\"rooms\":[")?;
+        for (id, room) in self.rooms.iter().enumerate() {
+            let exits: Vec<String> = self
+                .passages
+                .iter()
+                .filter(|passage| passage.from == id)
+                .map(|passage| {
+                    let exit = json::string(&passage.exit);
+                    match &passage.to {
+                        Destination::Room(to) => format!("{{\"exit\":{},\"room\":{}}}", exit, to),
+                        Destination::Halted(message) => {
+                            format!("{{\"exit\":{},\"halted\":{}}}", exit, json::string(message))
+                        }
+                        Destination::Unknown => format!("{{\"exit\":{}}}", exit),
+                    }
+                })
+                .collect();
+            let items: Vec<String> = room.items.iter().map(|item| json::string(item)).collect();
+            writeln!(
+                out,
+                "{{\"id\":{},\"name\":{},\"description\":{},\"items\":[{}],\"exits\":[{}]}}{}",
+                id,
+                json::string(&room.name),
+                json::string(&room.description),
+                items.join(","),
+                exits.join(","),
+                if id + 1 < self.rooms.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+fn dot_string(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary;
+
+    const FOOTHILLS: &str = "\
+What do you do?
+look
+
+== Foothills ==
+You find yourself standing at the base of an enormous mountain.
+
+Things of interest here:
+- tablet
+
+There are 2 exits:
+- doorway
+- south
+
+What do you do?
+";
+
+    #[test]
+    fn parse_rooms() {
+        assert_eq!(
+            Room::parse(FOOTHILLS),
+            Some(Room {
+                name: "Foothills".to_string(),
+                description: "You find yourself standing at the base of an enormous mountain."
+                    .to_string(),
+                items: vec!["tablet".to_string()],
+                exits: vec!["doorway".to_string(), "south".to_string()],
+            })
+        );
+        let room = Room::parse("== Ledge ==\nA ledge.\n\nThere is 1 exit:\n- down\n").unwrap();
+        assert_eq!(room.exits, vec!["down".to_string()]);
+        assert!(room.items.is_empty());
+        assert_eq!(Room::parse("You can't go that way."), None);
+    }
+
+    #[test]
+    fn write_maps() {
+        let map = Map {
+            rooms: vec![Room::parse(FOOTHILLS).unwrap()],
+            passages: vec![
+                Passage {
+                    from: 0,
+                    exit: "doorway".to_string(),
+                    to: Destination::Room(0),
+                },
+                Passage {
+                    from: 0,
+                    exit: "south".to_string(),
+                    to: Destination::Halted("You \"died\".".to_string()),
+                },
+            ],
+        };
+
+        let mut dot = vec![];
+        map.write_dot(&mut dot).unwrap();
+        assert_eq!(
+            String::from_utf8(dot).unwrap(),
+            "digraph map {\n    \
+             room0 [label=\"Foothills\\n(tablet)\"];\n    \
+             room0 -> room0 [label=\"doorway\"];\n    \
+             end1 [shape=box, label=\"You \\\"died\\\".\"];\n    \
+             room0 -> end1 [label=\"south\"];\n}\n"
+        );
+
+        let mut json = vec![];
+        map.write_json(&mut json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            "{\"rooms\":[\n\
+             {\"id\":0,\"name\":\"Foothills\",\"description\":\"You find yourself standing at \
+             the base of an enormous mountain.\",\"items\":[\"tablet\"],\"exits\":[\
+             {\"exit\":\"doorway\",\"room\":0},{\"exit\":\"south\",\"halted\":\"You \\\"died\\\".\"}]}\n\
+             ]}\n"
+        );
+    }
+
+    #[test]
+    fn explore_the_challenge() {
+        let program = binary::challenge();
+        let io = Scripted::new();
+        let mut vm = VM::boot(io.clone()).load_program(program).unwrap();
+
+        let map = explore(&mut vm, &io, 4).unwrap();
+        let names: Vec<&str> = map.rooms.iter().map(|room| room.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Foothills", "Dark cave", "Foothills", "Dark cave"]
+        );
+        assert_eq!(
+            map.passages[0],
+            Passage {
+                from: 0,
+                exit: "doorway".to_string(),
+                to: Destination::Room(1),
+            }
+        );
+    }
+}
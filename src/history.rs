@@ -0,0 +1,348 @@
+//! Execution history for reverse debugging.
+//!
+//! Every executed instruction leaves an undo record with its effects, which
+//! are enough to revert it. Only the most recent records are kept, older
+//! steps are reached by restoring the closest full snapshot taken every
+//! `checkpoint_interval` steps and executing the program again from there.
+//!
+//! Input is not read again when going back: the characters consumed since
+//! the target step are pushed back to the input buffer, so the program sees
+//! the same input when it is resumed.
+
+use crate::{
+    device::Scripted,
+    opcode::Opcode,
+    snapshot::Snapshot,
+    vm::{Effect, StepEvent, VmError, VM},
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    error, fmt, mem,
+};
+
+const MAX_RECORDS: usize = 100_000;
+const CHECKPOINT_INTERVAL: u64 = 10_000;
+const MAX_CHECKPOINTS: usize = 256;
+
+#[derive(Debug)]
+pub struct History {
+    /// Effects of the most recent steps, the last one belongs to the step
+    /// right before the current one.
+    records: VecDeque<Vec<Effect>>,
+    /// Pointer of every step since the oldest checkpoint.
+    pointers: VecDeque<u16>,
+    /// Characters consumed since the oldest checkpoint with their step.
+    inputs: VecDeque<(u64, usize)>,
+    checkpoints: BTreeMap<u64, Snapshot>,
+    max_records: usize,
+    checkpoint_interval: u64,
+    max_checkpoints: usize,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    /// The step is older than the oldest checkpoint.
+    Unreachable {
+        step: u64,
+        oldest: Option<u64>,
+    },
+    /// The step has not been executed yet.
+    Future {
+        step: u64,
+        current: u64,
+    },
+    Vm(VmError),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryError::Unreachable {
+                step,
+                oldest: Some(oldest),
+            } => write!(
+                f,
+                "step {} is no longer in the history, the oldest is {}",
+                step, oldest
+            ),
+            HistoryError::Unreachable { step, oldest: None } => {
+                write!(f, "step {} is not in the history, it is empty", step)
+            }
+            HistoryError::Future { step, current } => {
+                write!(f, "step {} is ahead of the current step {}", step, current)
+            }
+            HistoryError::Vm(e) => write!(f, "replay failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for HistoryError {}
+
+impl From<VmError> for HistoryError {
+    fn from(e: VmError) -> Self {
+        HistoryError::Vm(e)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::with_limits(MAX_RECORDS, CHECKPOINT_INTERVAL, MAX_CHECKPOINTS)
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Keeps at most `max_records` undo records and `max_checkpoints`
+    /// snapshots, one every `checkpoint_interval` steps. The history reaches
+    /// back `max_checkpoints * checkpoint_interval` steps.
+    pub fn with_limits(
+        max_records: usize,
+        checkpoint_interval: u64,
+        max_checkpoints: usize,
+    ) -> History {
+        History {
+            records: VecDeque::new(),
+            pointers: VecDeque::new(),
+            inputs: VecDeque::new(),
+            checkpoints: BTreeMap::new(),
+            max_records,
+            checkpoint_interval: checkpoint_interval.max(1),
+            max_checkpoints: max_checkpoints.max(1),
+        }
+    }
+
+    /// The oldest step that can be returned to.
+    pub fn oldest(&self) -> Option<u64> {
+        self.checkpoints.keys().next().copied()
+    }
+
+    /// Forgets everything, used when the state of the VM is changed outside
+    /// of the program.
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.pointers.clear();
+        self.inputs.clear();
+        self.checkpoints.clear();
+    }
+
+    /// Executes one instruction and records it.
+    pub fn step(&mut self, vm: &mut VM) -> Result<StepEvent, VmError> {
+        let index = vm.steps();
+        if self.checkpoints.is_empty()
+            || (index.is_multiple_of(self.checkpoint_interval)
+                && !self.checkpoints.contains_key(&index))
+        {
+            self.checkpoint(vm);
+        }
+
+        let event = vm.step()?;
+        if event.instruction.opcode == Opcode::In
+            && !event
+                .effects
+                .iter()
+                .any(|effect| matches!(effect, Effect::Input(_)))
+        {
+            // the state was replaced by the `!load` meta-command
+            self.clear();
+            return Ok(event);
+        }
+
+        self.pointers.push_back(event.pointer as u16);
+        for effect in &event.effects {
+            if let Effect::Input(value) = effect {
+                self.inputs.push_back((index, *value));
+            }
+        }
+        self.records.push_back(event.effects.clone());
+        if self.records.len() > self.max_records {
+            self.records.pop_front();
+        }
+        Ok(event)
+    }
+
+    /// The most recent step before the current one that started at an
+    /// address matching the predicate.
+    pub fn rfind<P: Fn(usize) -> bool>(&self, vm: &VM, predicate: P) -> Option<u64> {
+        let oldest = self.oldest()?;
+        let pointers = self.pointers.len().min((vm.steps() - oldest) as usize);
+        self.pointers
+            .iter()
+            .take(pointers)
+            .rposition(|&pointer| predicate(pointer.into()))
+            .map(|offset| oldest + offset as u64)
+    }
+
+    /// Returns the VM to the state it had before executing the given step.
+    pub fn goto(&mut self, vm: &mut VM, step: u64) -> Result<(), HistoryError> {
+        let current = vm.steps();
+        if step > current {
+            return Err(HistoryError::Future { step, current });
+        }
+        let oldest = match self.oldest() {
+            Some(oldest) if oldest <= step => oldest,
+            oldest if step < current => return Err(HistoryError::Unreachable { step, oldest }),
+            _ => return Ok(()),
+        };
+
+        if current - step <= self.records.len() as u64 {
+            while vm.steps() > step {
+                let effects = self.records.pop_back().unwrap_or_default();
+                let pointer = self.pointers.pop_back().map_or(0, usize::from);
+                vm.undo(pointer, &effects);
+            }
+            self.truncate(step);
+            return Ok(());
+        }
+
+        let (&start, snapshot) = self
+            .checkpoints
+            .range(..=step)
+            .next_back()
+            .expect("the oldest checkpoint precedes the step");
+        let mut snapshot = snapshot.clone();
+        snapshot.input_buffer = vm.input_buffer().to_vec();
+        snapshot.input_buffer.extend(
+            self.inputs
+                .iter()
+                .rev()
+                .take_while(|(index, _)| *index >= start)
+                .map(|(_, value)| *value),
+        );
+
+        self.records.clear();
+        self.pointers.truncate((start - oldest) as usize);
+        self.truncate(start);
+        vm.restore(&snapshot);
+        vm.set_steps(start);
+
+        // replay quietly, without triggering the watchpoints again
+        let io = vm.replace_io(Box::new(Scripted::new()));
+        let watchpoints = mem::take(vm.watchpoints_mut());
+        let result = (start..step).try_for_each(|_| self.step(vm).map(|_| ()));
+        vm.replace_io(io);
+        *vm.watchpoints_mut() = watchpoints;
+        Ok(result?)
+    }
+
+    /// Drops everything recorded at or after the given step.
+    fn truncate(&mut self, step: u64) {
+        self.checkpoints.split_off(&(step + 1));
+        while self.inputs.back().is_some_and(|(index, _)| *index >= step) {
+            self.inputs.pop_back();
+        }
+    }
+
+    fn checkpoint(&mut self, vm: &VM) {
+        self.checkpoints.insert(vm.steps(), vm.snapshot());
+        if self.checkpoints.len() <= self.max_checkpoints {
+            return;
+        }
+
+        let dropped = *self.checkpoints.keys().next().unwrap();
+        self.checkpoints.remove(&dropped);
+        let oldest = *self.checkpoints.keys().next().unwrap();
+        self.pointers.drain(..(oldest - dropped) as usize);
+        while self
+            .inputs
+            .front()
+            .is_some_and(|(index, _)| *index < oldest)
+        {
+            self.inputs.pop_front();
+        }
+        let reachable = (vm.steps() - oldest) as usize;
+        if self.records.len() > reachable {
+            self.records.drain(..self.records.len() - reachable);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VmState;
+
+    const R0: u16 = 32768;
+    const R1: u16 = 32769;
+
+    // 0: in r0; 2: push r0; 4: add r1 r1 r0; 8: wmem 100 r1; 11: jmp 0
+    const PROGRAM: [u16; 13] = [20, R0, 2, R0, 9, R1, R1, R0, 16, 100, R1, 6, 0];
+
+    fn boot(input: &str) -> VM {
+        let io = Scripted::new();
+        io.push_line(input);
+        VM::boot(io).load_program(PROGRAM.to_vec()).unwrap()
+    }
+
+    fn run(history: &mut History, vm: &mut VM, steps: u64) {
+        for _ in 0..steps {
+            history.step(vm).unwrap();
+        }
+    }
+
+    #[test]
+    fn undo_records() {
+        let mut vm = boot("abc");
+        let mut history = History::new();
+        run(&mut history, &mut vm, 5);
+        let snapshot = vm.snapshot();
+        run(&mut history, &mut vm, 7);
+
+        history.goto(&mut vm, 5).unwrap();
+        assert_eq!(vm.steps(), 5);
+        assert_eq!(vm.snapshot(), snapshot);
+
+        run(&mut history, &mut vm, 10);
+        assert_eq!(vm.memory()[100], ('a' as u16 + 'b' as u16 + 'c' as u16));
+    }
+
+    #[test]
+    fn replays_from_checkpoints() {
+        let mut vm = boot("abcdef");
+        let mut history = History::with_limits(3, 4, 10);
+        run(&mut history, &mut vm, 9);
+        let snapshot = vm.snapshot();
+        run(&mut history, &mut vm, 14);
+
+        history.goto(&mut vm, 9).unwrap();
+        assert_eq!(vm.steps(), 9);
+        assert_eq!(vm.memory(), snapshot.memory.as_slice());
+        assert_eq!(vm.registers(), &snapshot.registers);
+        assert_eq!(vm.stack(), snapshot.stack.as_slice());
+        assert_eq!(vm.pointer(), snapshot.pointer);
+
+        run(&mut history, &mut vm, 25);
+        assert_eq!(vm.stack(), &[97, 98, 99, 100, 101, 102, 10]);
+    }
+
+    #[test]
+    fn limits() {
+        let mut vm = boot("abcdef");
+        let mut history = History::with_limits(3, 4, 2);
+        run(&mut history, &mut vm, 20);
+
+        assert_eq!(history.oldest(), Some(12));
+        assert!(matches!(
+            history.goto(&mut vm, 11),
+            Err(HistoryError::Unreachable { step: 11, .. })
+        ));
+        assert!(matches!(
+            history.goto(&mut vm, 21),
+            Err(HistoryError::Future { step: 21, .. })
+        ));
+        assert_eq!(history.rfind(&vm, |pointer| pointer == 0), Some(15));
+    }
+
+    #[test]
+    fn undoes_halt() {
+        let mut vm = VM::boot(Scripted::new()).load_program(vec![0]).unwrap();
+        let mut history = History::new();
+        history.step(&mut vm).unwrap();
+
+        history.goto(&mut vm, 0).unwrap();
+        assert_eq!(vm.state(), VmState::Running);
+        assert_eq!(vm.steps(), 0);
+    }
+}
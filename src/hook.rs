@@ -0,0 +1,96 @@
+//! Native replacements for guest subroutines.
+//!
+//! A hook registered at an address runs instead of the subroutine whenever a
+//! `call` targets that address, and execution continues right after the
+//! `call` as if the subroutine had returned. Changes made through the
+//! [`HookContext`] are recorded as effects of the `call` instruction, so they
+//! show up in traces and can be undone like any other instruction.
+
+use crate::vm::{VmError, VM};
+use std::{collections::BTreeMap, fmt};
+
+pub type Hook = Box<dyn FnMut(&mut HookContext) -> Result<(), VmError>>;
+
+/// The hooks of a VM by the address of the subroutine they replace.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: BTreeMap<usize, Hook>,
+}
+
+impl Hooks {
+    pub fn insert(&mut self, addr: usize, hook: Hook) {
+        self.hooks.insert(addr, hook);
+    }
+
+    pub fn remove(&mut self, addr: usize) -> bool {
+        self.hooks.remove(&addr).is_some()
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.hooks.contains_key(&addr)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        self.hooks.keys().copied()
+    }
+
+    /// Takes the hook out while it runs, so it can borrow the VM mutably.
+    pub(crate) fn take(&mut self, addr: usize) -> Option<Hook> {
+        self.hooks.remove(&addr)
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.hooks.keys()).finish()
+    }
+}
+
+/// Access to the state of the VM from a hook.
+pub struct HookContext<'a> {
+    vm: &'a mut VM,
+}
+
+impl<'a> HookContext<'a> {
+    pub(crate) fn new(vm: &'a mut VM) -> HookContext<'a> {
+        HookContext { vm }
+    }
+
+    /// Address of the `call` instruction.
+    pub fn pointer(&self) -> usize {
+        self.vm.pointer()
+    }
+
+    pub fn register(&self, register: usize) -> usize {
+        self.vm.registers()[register]
+    }
+
+    pub fn set_register(&mut self, register: usize, value: usize) {
+        self.vm.set_register(register, value);
+    }
+
+    pub fn read(&self, addr: usize) -> Result<usize, VmError> {
+        self.vm.read_memory(addr)
+    }
+
+    pub fn write(&mut self, addr: usize, value: usize) -> Result<(), VmError> {
+        self.vm.write_memory(addr, value)
+    }
+
+    pub fn stack(&self) -> &[usize] {
+        self.vm.stack()
+    }
+
+    pub fn push(&mut self, value: usize) {
+        self.vm.push_stack(value);
+    }
+
+    pub fn pop(&mut self) -> Result<usize, VmError> {
+        self.vm.pop_stack()
+    }
+
+    /// Writes a character to the output like `out` does.
+    pub fn output(&mut self, c: char) -> Result<(), VmError> {
+        self.vm.output_char(c)
+    }
+}
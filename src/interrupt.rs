@@ -0,0 +1,22 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Replaces the default Ctrl-C behaviour of terminating the process with
+/// setting a flag that can be polled with `take`.
+pub fn install() {
+    let handler = handle_sigint as extern "C" fn(libc::c_int);
+    // SAFETY: the handler only stores into an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+/// Returns whether Ctrl-C was pressed since the last call.
+pub fn take() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,24 @@
+pub mod asm;
+pub mod binary;
+pub mod cfg;
+pub mod codes;
+pub mod coins;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+pub mod explore;
+pub mod history;
+pub mod hook;
+pub mod interrupt;
+mod json;
+pub mod meta;
+pub mod opcode;
+pub mod patch;
+pub mod snapshot;
+pub mod strings;
+pub mod teleporter;
+pub mod trace;
+pub mod vault;
+pub mod vm;
+pub mod walkthrough;
+pub mod watch;
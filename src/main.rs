@@ -1,32 +1,319 @@
+use cli::{Command, Options, USAGE};
 use std::{
-    fs::File,
-    io::{BufReader, Read},
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    process,
+};
+use synacor_challenge_rs::{
+    asm,
+    binary::{read_binary, write_binary},
+    cfg,
+    codes::CodeExtractor,
+    coins,
+    debugger::Debugger,
+    device::{IoDevice, Scripted, Terminal},
+    disasm,
+    explore::{self, MapFormat},
+    patch::{self, Patch},
+    snapshot::Snapshot,
+    strings, teleporter,
+    trace::Tracer,
+    vault,
+    vm::{ExitReason, VmErrorKind, VM},
+    walkthrough::Walkthrough,
 };
-use vm::VM;
 
-mod vm;
+mod cli;
 
 fn main() {
-    simple_logging::log_to_file("vm.log", log::LevelFilter::Warn).unwrap();
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    simple_logging::log_to_file(&options.log_file, options.log_level).unwrap();
 
-    let file = File::open("challenge/challenge.bin").unwrap();
+    let result = match options.command {
+        Command::Run => run(&options, false),
+        Command::Debug => debug(&options),
+        Command::Trace => run(&options, true),
+        Command::Disasm => disassemble(&options),
+        Command::Asm => assemble(&options),
+        Command::Patch => patch(&options),
+        Command::Teleporter => teleporter(&options),
+        Command::Walkthrough => walkthrough(&options),
+        Command::Explore => explore(&options),
+        Command::Coins => solve_coins(&options),
+        Command::Vault => solve_vault(),
+        Command::Strings => dump_strings(&options),
+        Command::Cfg => control_flow(&options, false),
+        Command::Calls => control_flow(&options, true),
+    };
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+}
 
-    VM::boot().load_program(read_binary(file)).run();
+fn boot(options: &Options) -> Result<VM, Box<dyn Error>> {
+    let terminal = match &options.input_script {
+        Some(path) => Terminal::with_script(fs::read_to_string(path)?.lines()),
+        None => Terminal::new(),
+    };
+    load(options, terminal)
 }
 
-fn read_binary(file: File) -> Vec<usize> {
-    let buf = BufReader::new(file);
-    let mut result: Vec<usize> = Vec::new();
+/// Loads the program with the snapshot and the patches from the options.
+fn load<D: IoDevice + 'static>(options: &Options, io: D) -> Result<VM, Box<dyn Error>> {
+    let program = read_binary(File::open(&options.program)?)?;
+    let mut vm = VM::boot(io).load_program(program)?;
+
+    if let Some(path) = &options.load_snapshot {
+        vm.restore(&Snapshot::load(path)?);
+    }
+    for patch in load_patches(options)? {
+        patch.apply_to(&mut vm)?;
+    }
+    Ok(vm)
+}
 
-    let mut iter = buf.bytes();
+fn load_patches(options: &Options) -> Result<Vec<Patch>, Box<dyn Error>> {
+    let mut patches = Vec::new();
+    for path in &options.patches {
+        let source = fs::read_to_string(path)?;
+        patches.extend(patch::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+    Ok(patches)
+}
 
-    while let Some(low_byte_r) = iter.next() {
-        if let Some(high_byte_r) = iter.next() {
-            let low_byte = low_byte_r.unwrap();
-            let high_byte = high_byte_r.unwrap();
+fn run(options: &Options, trace: bool) -> Result<(), Box<dyn Error>> {
+    let mut vm = boot(options)?;
+    let mut tracer = if trace {
+        Some(Tracer::new(
+            BufWriter::new(File::create(&options.trace_output)?),
+            options.trace_format,
+            options.trace_filter.clone(),
+        ))
+    } else {
+        None
+    };
+    let mut codes = CodeExtractor::new();
 
-            result.push((((high_byte as u16) << 8) | low_byte as u16).into())
+    let result = loop {
+        if Some(vm.steps()) == options.max_steps {
+            break Ok(ExitReason::StepLimit);
         }
+        let event = match vm.step() {
+            Ok(event) => event,
+            Err(error) => break Err(error),
+        };
+        codes.observe(&event);
+        if let Some(tracer) = &mut tracer {
+            tracer.record(&event)?;
+        }
+        if let Some(reason) = event.exit {
+            break Ok(reason);
+        }
+    };
+    if let Some(tracer) = &mut tracer {
+        tracer.flush()?;
+    }
+
+    codes.finish();
+    if !codes.codes().is_empty() {
+        eprintln!("\ncodes found:");
+        codes.write_report(io::stderr())?;
+    }
+    if let Some(path) = &options.codes_report {
+        codes.write_report(File::create(path)?)?;
+    }
+
+    if result? == ExitReason::StepLimit {
+        eprintln!("stopped after {} steps at {}", vm.steps(), vm.pointer());
+    }
+    Ok(())
+}
+
+fn debug(options: &Options) -> Result<(), Box<dyn Error>> {
+    let mut debugger = Debugger::new(boot(options)?);
+    debugger.repl(&mut Terminal::new(), &mut io::stdout())?;
+    Ok(())
+}
+
+fn disassemble(options: &Options) -> Result<(), Box<dyn Error>> {
+    let program = read_binary(File::open(&options.program)?)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for line in disasm::disassemble(&program, 0) {
+        if writeln!(out, "{}", line).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn assemble(options: &Options) -> Result<(), Box<dyn Error>> {
+    let source = fs::read_to_string(&options.program)?;
+    let program = asm::assemble(&source)
+        .map_err(|error| format!("{}: {}", options.program.display(), error))?;
+    write_binary(File::create(&options.output)?, &program)?;
+    Ok(())
+}
+
+fn patch(options: &Options) -> Result<(), Box<dyn Error>> {
+    let patches = load_patches(options)?;
+    if patches.is_empty() {
+        return Err("no patches given, use --patch <FILE>".into());
+    }
+    let mut program = read_binary(File::open(&options.program)?)?;
+    patch::apply_all(&patches, &mut program)?;
+    write_binary(File::create(&options.output)?, &program)?;
+
+    for patch in &patches {
+        println!("{:05}: {} {}", patch.address, patch.name, patch.description);
+    }
+    Ok(())
+}
+
+fn teleporter(options: &Options) -> Result<(), Box<dyn Error>> {
+    let program = read_binary(File::open(&options.program)?)?;
+    let confirmation =
+        teleporter::find(&program).ok_or("the program has no teleporter confirmation")?;
+    println!(
+        "{:05}: call {} with r0={} r1={}, expecting r0={}",
+        confirmation.call_site,
+        confirmation.routine,
+        confirmation.r0,
+        confirmation.r1,
+        confirmation.expected
+    );
+
+    let solutions = teleporter::solve(&confirmation, 1..32768);
+    if solutions.is_empty() {
+        return Err(format!(
+            "no value of r{} passes the confirmation",
+            confirmation.register
+        )
+        .into());
+    }
+    for value in solutions {
+        println!("r{}={}", confirmation.register, value);
+    }
+    Ok(())
+}
+
+fn walkthrough(options: &Options) -> Result<(), Box<dyn Error>> {
+    let path = options
+        .walkthrough
+        .as_ref()
+        .ok_or("no script given, use --walkthrough <FILE>")?;
+    let walkthrough = Walkthrough::parse(&fs::read_to_string(path)?)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let io = Scripted::new();
+    let mut vm = load(options, io.clone())?;
+
+    let report = walkthrough
+        .run(&mut vm, &io)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    for capture in &report.captures {
+        println!("{}:{}: {}", path.display(), capture.line, capture.text);
+    }
+    println!(
+        "{} inputs, {} expectations met{}",
+        report.inputs,
+        report.expectations,
+        if report.halted {
+            ", program halted"
+        } else {
+            ""
+        }
+    );
+    Ok(())
+}
+
+fn explore(options: &Options) -> Result<(), Box<dyn Error>> {
+    let io = Scripted::new();
+    let mut vm = load(options, io.clone())?;
+    let map = explore::explore(&mut vm, &io, options.max_rooms)?;
+    eprintln!("{} rooms, {} passages", map.rooms.len(), map.passages.len());
+
+    let stdout = io::stdout();
+    let out = BufWriter::new(stdout.lock());
+    match options.map_format {
+        MapFormat::Dot => map.write_dot(out)?,
+        MapFormat::Json => map.write_json(out)?,
+    }
+    Ok(())
+}
+
+fn solve_coins(options: &Options) -> Result<(), Box<dyn Error>> {
+    let io = match &options.input_script {
+        Some(path) => Scripted::with_input(fs::read_to_string(path)?.lines()),
+        None => Scripted::new(),
+    };
+    let mut vm = load(options, io)?;
+    match vm.run() {
+        Err(e) if e.kind != VmErrorKind::InputExhausted => return Err(e.into()),
+        _ => {}
+    }
+
+    let found = coins::inspect(&mut vm)?;
+    let order = coins::solve(&found)
+        .ok_or_else(|| format!("no solution with {} coins in the inventory", found.len()))?;
+    for command in coins::commands(&order) {
+        println!("{}", command);
+    }
+    Ok(())
+}
+
+fn solve_vault() -> Result<(), Box<dyn Error>> {
+    let path = vault::Grid::parse(vault::CHALLENGE)?
+        .solve()
+        .ok_or("the orb cannot reach the vault door")?;
+    for command in vault::commands(&path) {
+        println!("{}", command);
+    }
+    Ok(())
+}
+
+fn dump_strings(options: &Options) -> Result<(), Box<dyn Error>> {
+    let program = read_binary(File::open(&options.program)?)?;
+    let texts = strings::extract(&program)?;
+
+    let stdout = io::stdout();
+    strings::write_texts(&texts, BufWriter::new(stdout.lock()))?;
+    Ok(())
+}
+
+/// Analyzes the code reachable from the start of the program, the current
+/// pointer of a restored snapshot and the `--function` option.
+fn control_flow(options: &Options, calls: bool) -> Result<(), Box<dyn Error>> {
+    let vm = load(options, Scripted::new())?;
+    let memory = if options.decrypt {
+        strings::decrypt(vm.memory())?
+    } else {
+        vm.memory().to_vec()
+    };
+    let mut entries = vec![0, vm.pointer()];
+    entries.extend(options.function);
+    let analysis = cfg::analyze(&memory, &entries);
+    analysis.write_summary(io::stderr())?;
+
+    let stdout = io::stdout();
+    let out = BufWriter::new(stdout.lock());
+    if calls {
+        analysis.write_call_graph_dot(out)?;
+    } else {
+        analysis.write_cfg_dot(out, options.function)?;
     }
-    result
+    Ok(())
 }
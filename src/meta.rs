@@ -0,0 +1,57 @@
+/// Commands typed at the `in` prompt that start with `!`. They are handled by
+/// the VM and never reach the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetaCommand {
+    Save(String),
+    Load(String),
+}
+
+impl MetaCommand {
+    /// Parses a line of input, returns `None` when it is not a meta-command.
+    pub fn parse(line: &str) -> Option<Result<MetaCommand, String>> {
+        let line = line.trim();
+        let command = line.strip_prefix('!')?;
+        let mut words = command.split_whitespace();
+
+        let result = match (words.next(), words.next(), words.next()) {
+            (Some("save"), Some(name), None) => snapshot_name(name).map(MetaCommand::Save),
+            (Some("load"), Some(name), None) => snapshot_name(name).map(MetaCommand::Load),
+            (Some("save"), _, _) => Err("usage: !save <name>".to_string()),
+            (Some("load"), _, _) => Err("usage: !load <name>".to_string()),
+            _ => Err(format!("unknown command: {}", line)),
+        };
+        Some(result)
+    }
+}
+
+fn snapshot_name(name: &str) -> Result<String, String> {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid snapshot name: {}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(MetaCommand::parse("take tablet\n"), None);
+        assert_eq!(
+            MetaCommand::parse("!save before-maze\n"),
+            Some(Ok(MetaCommand::Save("before-maze".to_string())))
+        );
+        assert_eq!(
+            MetaCommand::parse("!load x"),
+            Some(Ok(MetaCommand::Load("x".to_string())))
+        );
+        assert!(matches!(MetaCommand::parse("!load ../x"), Some(Err(_))));
+        assert!(matches!(MetaCommand::parse("!save"), Some(Err(_))));
+        assert!(matches!(MetaCommand::parse("!dance"), Some(Err(_))));
+    }
+}
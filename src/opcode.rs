@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// The operations of the architecture, in opcode order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Halt,
+    Set,
+    Push,
+    Pop,
+    Eq,
+    Gt,
+    Jmp,
+    Jt,
+    Jf,
+    Add,
+    Mult,
+    Mod,
+    And,
+    Or,
+    Not,
+    Rmem,
+    Wmem,
+    Call,
+    Ret,
+    Out,
+    In,
+    Noop,
+}
+
+/// Mnemonic and number of operands of every opcode, indexed by the opcode.
+const TABLE: [(Opcode, &str, usize); 22] = [
+    (Opcode::Halt, "halt", 0),
+    (Opcode::Set, "set", 2),
+    (Opcode::Push, "push", 1),
+    (Opcode::Pop, "pop", 1),
+    (Opcode::Eq, "eq", 3),
+    (Opcode::Gt, "gt", 3),
+    (Opcode::Jmp, "jmp", 1),
+    (Opcode::Jt, "jt", 2),
+    (Opcode::Jf, "jf", 2),
+    (Opcode::Add, "add", 3),
+    (Opcode::Mult, "mult", 3),
+    (Opcode::Mod, "mod", 3),
+    (Opcode::And, "and", 3),
+    (Opcode::Or, "or", 3),
+    (Opcode::Not, "not", 2),
+    (Opcode::Rmem, "rmem", 2),
+    (Opcode::Wmem, "wmem", 2),
+    (Opcode::Call, "call", 1),
+    (Opcode::Ret, "ret", 0),
+    (Opcode::Out, "out", 1),
+    (Opcode::In, "in", 1),
+    (Opcode::Noop, "noop", 0),
+];
+
+impl Opcode {
+    pub fn from_code(code: usize) -> Option<Opcode> {
+        TABLE.get(code).map(|(opcode, _, _)| *opcode)
+    }
+
+    pub fn from_name(name: &str) -> Option<Opcode> {
+        TABLE
+            .iter()
+            .find(|(_, mnemonic, _)| *mnemonic == name)
+            .map(|(opcode, _, _)| *opcode)
+    }
+
+    pub fn code(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        TABLE[self.code()].1
+    }
+
+    pub fn arity(self) -> usize {
+        TABLE[self.code()].2
+    }
+
+    /// Number of words the instruction occupies, including the opcode itself.
+    pub fn width(self) -> usize {
+        self.arity() + 1
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
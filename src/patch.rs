@@ -0,0 +1,366 @@
+//! Named binary patches that check the words they replace.
+//!
+//! A patch file holds any number of sections like
+//!
+//! ```text
+//! # comment
+//! [skip-confirmation]
+//! description = Set r0 to 6 instead of calling the confirmation routine
+//! address = 5483
+//! original = 1 32768 4 1 32769 1 17 6027
+//! replacement = 1 32768 6 21 21 21 21 21
+//! ```
+//!
+//! Words are decimal or `0x` hexadecimal numbers. A patch is only applied
+//! when the memory holds either the original or the replacement words, so
+//! applying it twice is harmless but patching the wrong binary is an error.
+
+use crate::vm::{VmError, VM};
+use std::{convert::TryFrom, error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub name: String,
+    pub description: String,
+    pub address: usize,
+    pub original: Vec<u16>,
+    pub replacement: Vec<u16>,
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    OutOfBounds {
+        patch: String,
+    },
+    Mismatch {
+        patch: String,
+        addr: usize,
+        expected: u16,
+        found: u16,
+    },
+    /// Two patches replace some of the same words.
+    Overlap {
+        first: String,
+        second: String,
+    },
+    Vm(VmError),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            PatchError::OutOfBounds { patch } => {
+                write!(f, "patch '{}' goes past the end of the memory", patch)
+            }
+            PatchError::Mismatch {
+                patch,
+                addr,
+                expected,
+                found,
+            } => write!(
+                f,
+                "patch '{}' expected {} at {} but found {}",
+                patch, expected, addr, found
+            ),
+            PatchError::Overlap { first, second } => {
+                write!(f, "patches '{}' and '{}' overlap", first, second)
+            }
+            PatchError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for PatchError {}
+
+impl From<VmError> for PatchError {
+    fn from(e: VmError) -> Self {
+        PatchError::Vm(e)
+    }
+}
+
+impl Patch {
+    /// Returns whether the patch still has to be applied, or an error when
+    /// the memory holds neither the original nor the replacement words.
+    pub fn check(&self, memory: &[u16]) -> Result<bool, PatchError> {
+        let words = self
+            .address
+            .checked_add(self.original.len())
+            .and_then(|end| memory.get(self.address..end))
+            .ok_or_else(|| PatchError::OutOfBounds {
+                patch: self.name.clone(),
+            })?;
+        if words == self.replacement.as_slice() {
+            return Ok(false);
+        }
+        match words.iter().zip(&self.original).position(|(a, b)| a != b) {
+            Some(offset) => Err(PatchError::Mismatch {
+                patch: self.name.clone(),
+                addr: self.address + offset,
+                expected: self.original[offset],
+                found: words[offset],
+            }),
+            None => Ok(true),
+        }
+    }
+
+    pub fn apply(&self, memory: &mut [u16]) -> Result<(), PatchError> {
+        if self.check(memory)? {
+            memory[self.address..self.address + self.replacement.len()]
+                .copy_from_slice(&self.replacement);
+        }
+        Ok(())
+    }
+
+    /// Applies the patch to the memory of a running VM.
+    pub fn apply_to(&self, vm: &mut VM) -> Result<(), PatchError> {
+        if self.check(vm.memory())? {
+            for (addr, word) in (self.address..).zip(&self.replacement) {
+                vm.poke(addr, *word)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks every patch before applying any of them. Patches must not
+/// overlap, as the original words of one would be checked before the other
+/// replaced them.
+pub fn apply_all(patches: &[Patch], memory: &mut [u16]) -> Result<(), PatchError> {
+    let mut sorted: Vec<&Patch> = patches.iter().collect();
+    sorted.sort_by_key(|patch| patch.address);
+    for pair in sorted.windows(2) {
+        if pair[0].address.saturating_add(pair[0].original.len()) > pair[1].address {
+            return Err(PatchError::Overlap {
+                first: pair[0].name.clone(),
+                second: pair[1].name.clone(),
+            });
+        }
+    }
+    for patch in patches {
+        patch.check(memory)?;
+    }
+    for patch in patches {
+        patch.apply(memory)?;
+    }
+    Ok(())
+}
+
+pub fn parse(source: &str) -> Result<Vec<Patch>, PatchError> {
+    let mut patches = Vec::new();
+    // the patch being parsed with the line of its header
+    let mut current: Option<(usize, Patch)> = None;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| PatchError::Syntax { line, message };
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            if let Some(patch) = current.take() {
+                patches.push(finish(patch)?);
+            }
+            let name = name.trim();
+            if name.is_empty() || patches.iter().any(|patch: &Patch| patch.name == name) {
+                return Err(error(format!("invalid or duplicate patch name: {}", name)));
+            }
+            current = Some((
+                line,
+                Patch {
+                    name: name.to_string(),
+                    description: String::new(),
+                    address: usize::MAX,
+                    original: vec![],
+                    replacement: vec![],
+                },
+            ));
+            continue;
+        }
+
+        let (key, value) = text
+            .split_once('=')
+            .ok_or_else(|| error(format!("expected key = value: {}", text)))?;
+        let patch = match &mut current {
+            Some((_, patch)) => patch,
+            None => return Err(error("expected a [name] header".to_string())),
+        };
+        let value = value.trim();
+        match key.trim() {
+            "description" => patch.description = value.to_string(),
+            "address" => patch.address = number(value).map_err(error)?,
+            "original" => patch.original = words(value).map_err(error)?,
+            "replacement" => patch.replacement = words(value).map_err(error)?,
+            key => return Err(error(format!("unknown key: {}", key))),
+        }
+    }
+
+    if let Some(patch) = current {
+        patches.push(finish(patch)?);
+    }
+    Ok(patches)
+}
+
+fn finish((line, patch): (usize, Patch)) -> Result<Patch, PatchError> {
+    let error = |message: &str| PatchError::Syntax {
+        line,
+        message: format!("patch '{}' {}", patch.name, message),
+    };
+    if patch.address == usize::MAX {
+        return Err(error("has no address"));
+    }
+    if patch.original.is_empty() {
+        return Err(error("has no original words"));
+    }
+    if patch.original.len() != patch.replacement.len() {
+        return Err(error(
+            "must have as many replacement words as original words",
+        ));
+    }
+    Ok(patch)
+}
+
+fn number(word: &str) -> Result<usize, String> {
+    let result = match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    result.map_err(|_| format!("invalid number: {}", word))
+}
+
+fn words(text: &str) -> Result<Vec<u16>, String> {
+    text.split_whitespace()
+        .map(|word| {
+            number(word)
+                .and_then(|n| u16::try_from(n).map_err(|_| format!("word out of range: {}", word)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary, device::Scripted};
+    use std::fs;
+
+    const SOURCE: &str = "\
+# two patches
+[first]
+description = Replace the first two words
+address = 0
+original = 1 2
+replacement = 0x10 0x20
+
+[second]
+address = 3
+original = 4
+replacement = 40
+";
+
+    #[test]
+    fn parse_patches() {
+        let patches = parse(SOURCE).unwrap();
+        assert_eq!(
+            patches[0],
+            Patch {
+                name: "first".to_string(),
+                description: "Replace the first two words".to_string(),
+                address: 0,
+                original: vec![1, 2],
+                replacement: vec![16, 32],
+            }
+        );
+        assert_eq!(patches[1].address, 3);
+
+        let line = |source| match parse(source) {
+            Err(PatchError::Syntax { line, .. }) => line,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(line("address = 1"), 1);
+        assert_eq!(line("[a]\naddress = 1\noriginal = 1\n"), 1);
+        assert_eq!(line("[a]\nsize = 1"), 2);
+        assert_eq!(line("[a]\noriginal = 65536"), 2);
+    }
+
+    #[test]
+    fn apply_checks_the_original_words() {
+        let patches = parse(SOURCE).unwrap();
+        let mut memory = vec![1, 2, 3, 4];
+        apply_all(&patches, &mut memory).unwrap();
+        assert_eq!(memory, vec![16, 32, 3, 40]);
+
+        // applying twice is fine
+        apply_all(&patches, &mut memory).unwrap();
+        assert_eq!(memory, vec![16, 32, 3, 40]);
+
+        let mut memory = vec![1, 2, 3, 5];
+        assert!(matches!(
+            apply_all(&patches, &mut memory),
+            Err(PatchError::Mismatch {
+                addr: 3,
+                expected: 4,
+                found: 5,
+                ..
+            })
+        ));
+        assert_eq!(memory, vec![1, 2, 3, 5]);
+        assert!(matches!(
+            patches[1].apply(&mut [4]),
+            Err(PatchError::OutOfBounds { .. })
+        ));
+        let far = Patch {
+            address: usize::MAX,
+            ..patches[1].clone()
+        };
+        assert!(matches!(
+            far.apply(&mut [4]),
+            Err(PatchError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn overlapping_patches() {
+        let mut patches = parse(SOURCE).unwrap();
+        patches.push(Patch {
+            name: "third".to_string(),
+            description: String::new(),
+            address: 1,
+            original: vec![2, 3],
+            replacement: vec![20, 30],
+        });
+        let mut memory = vec![1, 2, 3, 4];
+        match apply_all(&patches, &mut memory) {
+            Err(PatchError::Overlap { first, second }) => {
+                assert_eq!((first.as_str(), second.as_str()), ("first", "third"))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(memory, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn apply_to_a_running_vm() {
+        let patches = parse(SOURCE).unwrap();
+        let mut vm = VM::boot(Scripted::new())
+            .load_program(vec![1, 2, 3, 4])
+            .unwrap();
+        patches[1].apply_to(&mut vm).unwrap();
+        assert_eq!(&vm.memory()[..4], &[1, 2, 3, 40]);
+    }
+
+    #[test]
+    fn teleporter_patches_apply_to_the_challenge() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let source = fs::read_to_string(format!("{}/patches/teleporter.patch", dir)).unwrap();
+        let mut program = binary::challenge();
+
+        apply_all(&parse(&source).unwrap(), &mut program).unwrap();
+        assert_eq!(&program[5451..5454], &[1, 32775, 25734]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::{
+    convert::TryInto,
+    error, fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+const MAGIC: &[u8; 8] = b"SYNSNAP\0";
+
+/// Version of the on-disk format, bumped whenever the layout changes.
+pub const VERSION: u16 = 1;
+
+/// The complete state of a VM that is needed to resume the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub memory: Vec<u16>,
+    pub registers: [usize; 8],
+    pub stack: Vec<usize>,
+    pub pointer: usize,
+    pub input_buffer: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    ValueOutOfRange(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "i/o error: {}", e),
+            SnapshotError::InvalidMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::ValueOutOfRange(value) => {
+                write!(f, "value {} does not fit into a word", value)
+            }
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl Snapshot {
+    /// Writes the snapshot in the versioned binary format: the magic bytes,
+    /// the version, then every field as little-endian words, with vectors
+    /// prefixed by their length.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> Result<(), SnapshotError> {
+        writer.write_all(MAGIC)?;
+        write_word(&mut writer, VERSION.into())?;
+        write_word(&mut writer, self.pointer)?;
+        for register in &self.registers {
+            write_word(&mut writer, *register)?;
+        }
+        write_words(&mut writer, self.memory.iter().map(|word| *word as usize))?;
+        write_words(&mut writer, self.stack.iter().copied())?;
+        write_words(&mut writer, self.input_buffer.iter().copied())?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        let version = read_word(&mut reader)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let pointer = read_word(&mut reader)?.into();
+        let mut registers = [0; 8];
+        for register in registers.iter_mut() {
+            *register = read_word(&mut reader)?.into();
+        }
+        let memory = read_words(&mut reader)?;
+        let stack = read_words(&mut reader)?
+            .into_iter()
+            .map(usize::from)
+            .collect();
+        let input_buffer = read_words(&mut reader)?
+            .into_iter()
+            .map(usize::from)
+            .collect();
+
+        Ok(Snapshot {
+            memory,
+            registers,
+            stack,
+            pointer,
+            input_buffer,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        Snapshot::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+fn write_word<W: Write>(writer: &mut W, value: usize) -> Result<(), SnapshotError> {
+    let word: u16 = value
+        .try_into()
+        .map_err(|_| SnapshotError::ValueOutOfRange(value))?;
+    writer.write_all(&word.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_words<W, I>(writer: &mut W, values: I) -> Result<(), SnapshotError>
+where
+    W: Write,
+    I: ExactSizeIterator<Item = usize>,
+{
+    let len = values.len() as u32;
+    writer.write_all(&len.to_le_bytes())?;
+    for value in values {
+        write_word(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_word<R: Read>(reader: &mut R) -> Result<u16, SnapshotError> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_words<R: Read>(reader: &mut R) -> Result<Vec<u16>, SnapshotError> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    (0..u32::from_le_bytes(len))
+        .map(|_| read_word(reader))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        let mut memory = vec![0; 32768];
+        memory[0] = 21;
+        memory[32767] = 65535;
+        Snapshot {
+            memory,
+            registers: [1, 2, 3, 4, 5, 6, 7, 32775],
+            stack: vec![10, 20],
+            pointer: 1234,
+            input_buffer: vec!['\n' as usize, 'a' as usize],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = vec![];
+        snapshot().write_to(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[0..8], MAGIC);
+        assert_eq!(Snapshot::read_from(&bytes[..]).unwrap(), snapshot());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let result = Snapshot::read_from(&b"not a snapshot"[..]);
+
+        assert!(matches!(result, Err(SnapshotError::InvalidMagic)));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut bytes = vec![];
+        snapshot().write_to(&mut bytes).unwrap();
+        bytes[8] = 99;
+
+        assert!(matches!(
+            Snapshot::read_from(&bytes[..]),
+            Err(SnapshotError::UnsupportedVersion(99))
+        ));
+    }
+}
@@ -0,0 +1,385 @@
+//! Static extraction of the text of a program.
+//!
+//! The challenge decrypts most of its memory at boot, with a routine that
+//! rewrites every word from an address to the end of the program, and prints
+//! some strings through a callback that xors each character with a key the
+//! caller computes. The extractor emulates the decryption routine on a copy
+//! of the memory, then emulates every call to the string printing routine
+//! that passes a constant address, and at last collects the length-prefixed
+//! strings that are readable as they are, like the names and descriptions of
+//! the rooms.
+
+use crate::{
+    device::Scripted,
+    disasm::{self, Item},
+    opcode::Opcode,
+    snapshot::Snapshot,
+    vm::{ExitReason, Instruction, Value, VmError, VmErrorKind, VM},
+};
+use std::{
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+/// Instructions a routine may take in the sandbox before it is given up.
+const MAX_STEPS: u64 = 1_000_000;
+
+/// Instructions searched for the shape of a routine from its entry.
+const MAX_ROUTINE_LEN: usize = 32;
+
+/// Instructions before a call searched for setting `r0` to the string.
+const MAX_SETUP_LEN: usize = 3;
+
+/// Shorter strings are too likely to be code or numbers.
+const MIN_PLAIN_LEN: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub addr: usize,
+    /// The call that prints the text through the printing routine, `None`
+    /// for text stored as it is printed.
+    pub call_site: Option<usize>,
+    pub text: String,
+}
+
+/// Returns the texts of the program ordered by address.
+pub fn extract(memory: &[u16]) -> Result<Vec<Text>, VmError> {
+    let memory = decrypt(memory)?;
+    let mut sandbox = Sandbox::new(&memory)?;
+    let mut texts = Vec::new();
+
+    if let Some(printer) = find_printer(&memory) {
+        let targets: Vec<usize> = std::iter::once(printer)
+            .chain(
+                call_targets(&memory)
+                    .into_iter()
+                    .filter(|&target| calls(&memory, target, printer)),
+            )
+            .collect();
+        for (start, addr, call_site) in call_sites(&memory, &targets) {
+            if let Some(text) = sandbox.output(start, call_site + Opcode::Call.width()) {
+                // the whole string was printed, not some other text, and the
+                // key did not depend on registers the sandbox cannot know
+                if !text.is_empty()
+                    && memory.get(addr) == Some(&(text.chars().count() as u16))
+                    && text.chars().all(printable)
+                {
+                    texts.push(Text {
+                        addr,
+                        call_site: Some(call_site),
+                        text,
+                    });
+                }
+            }
+        }
+    }
+
+    let mut addr = 0;
+    while addr < memory.len() {
+        match plain(&memory, addr) {
+            Some(text) => {
+                let len = text.chars().count();
+                if !texts.iter().any(|found| found.addr == addr) {
+                    texts.push(Text {
+                        addr,
+                        call_site: None,
+                        text,
+                    });
+                }
+                addr += len + 1;
+            }
+            None => addr += 1,
+        }
+    }
+
+    texts.sort_by_key(|text| (text.addr, text.call_site));
+    Ok(texts)
+}
+
+/// Writes one text per line, with the call that prints it for the texts
+/// that are only readable through the printing routine.
+pub fn write_texts<W: Write>(texts: &[Text], mut out: W) -> io::Result<()> {
+    for text in texts {
+        match text.call_site {
+            Some(call_site) => writeln!(
+                out,
+                "{:05} @{:05}: {}",
+                text.addr,
+                call_site,
+                disasm::quote(&text.text)
+            )?,
+            None => writeln!(out, "{:05}: {}", text.addr, disasm::quote(&text.text))?,
+        }
+    }
+    Ok(())
+}
+
+/// Emulates the decryption routine on a copy of the memory, returns the copy
+/// as it is when there is none.
+pub fn decrypt(memory: &[u16]) -> Result<Vec<u16>, VmError> {
+    match find_decryptor(memory) {
+        Some(entry) => Sandbox::new(memory)?.decrypt(entry),
+        None => Ok(memory.to_vec()),
+    }
+}
+
+/// Looks for a routine that rewrites the memory in a loop:
+///
+/// ```text
+/// wmem r1 r0
+/// add r1 r1 1
+/// eq r0 30050 r1
+/// jf r0 1730
+/// ```
+pub fn find_decryptor(memory: &[u16]) -> Option<usize> {
+    call_targets(memory).into_iter().find(|&entry| {
+        routine(memory, entry).windows(4).any(|window| {
+            let [(wmem_addr, wmem), (_, add), (_, eq), (_, jump)] = window else {
+                return false;
+            };
+            let pointer = match (wmem.opcode, wmem.operands.as_slice()) {
+                (Opcode::Wmem, [Value::Register(pointer), _]) => *pointer,
+                _ => return false,
+            };
+            let flag = match (eq.opcode, eq.operands.as_slice()) {
+                (Opcode::Eq, [Value::Register(flag), a, b])
+                    if *a == Value::Register(pointer) || *b == Value::Register(pointer) =>
+                {
+                    *flag
+                }
+                _ => return false,
+            };
+            add.opcode == Opcode::Add
+                && add.operands
+                    == [
+                        Value::Register(pointer),
+                        Value::Register(pointer),
+                        Value::Number(1),
+                    ]
+                && matches!(
+                    (jump.opcode, jump.operands.as_slice()),
+                    (Opcode::Jt | Opcode::Jf, [Value::Register(r), Value::Number(target)])
+                        if *r == flag && *target <= *wmem_addr
+                )
+        })
+    })
+}
+
+/// Looks for the routine that calls the callback in `r1` with every
+/// character of the length-prefixed string at `r0`.
+pub fn find_printer(memory: &[u16]) -> Option<usize> {
+    call_targets(memory).into_iter().find(|&entry| {
+        let instructions = routine(memory, entry);
+        let reads_length = instructions.iter().any(|(_, instruction)| {
+            instruction.opcode == Opcode::Rmem && instruction.operands[1] == Value::Register(0)
+        });
+        let calls_back = instructions.iter().any(|(_, instruction)| {
+            instruction.opcode == Opcode::Call
+                && matches!(instruction.operands[0], Value::Register(_))
+        });
+        reads_length && calls_back
+    })
+}
+
+/// The targets of the `call` instructions with a constant address, in
+/// ascending order.
+fn call_targets(memory: &[u16]) -> Vec<usize> {
+    let mut targets: Vec<usize> = disasm::disassemble(memory, 0)
+        .into_iter()
+        .filter_map(|line| match line.item {
+            Item::Instruction(Instruction {
+                opcode: Opcode::Call,
+                operands,
+            }) => match operands.as_slice() {
+                [Value::Number(target)] if *target < memory.len() => Some(*target),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    targets
+}
+
+/// The instructions from `entry` in memory order, up to the first `ret`.
+fn routine(memory: &[u16], entry: usize) -> Vec<(usize, Instruction)> {
+    let mut instructions = Vec::new();
+    let mut addr = entry;
+    while addr < memory.len() && instructions.len() < MAX_ROUTINE_LEN {
+        let instruction = match disasm::decode(memory, addr) {
+            Some(instruction) => instruction,
+            None => break,
+        };
+        let width = instruction.opcode.width();
+        let ret = instruction.opcode == Opcode::Ret;
+        instructions.push((addr, instruction));
+        if ret {
+            break;
+        }
+        addr += width;
+    }
+    instructions
+}
+
+/// Whether the routine at `entry` calls `target` directly.
+fn calls(memory: &[u16], entry: usize, target: usize) -> bool {
+    entry != target
+        && routine(memory, entry).iter().any(|(_, instruction)| {
+            instruction.opcode == Opcode::Call && instruction.operands[0] == Value::Number(target)
+        })
+}
+
+/// Calls to one of the targets shortly after setting `r0` to a constant, as
+/// the address the emulation starts at, the value of `r0` and the address of
+/// the call.
+fn call_sites(memory: &[u16], targets: &[usize]) -> Vec<(usize, usize, usize)> {
+    let lines = disasm::disassemble(memory, 0);
+    let mut sites = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let target = match &line.item {
+            Item::Instruction(instruction) if instruction.opcode == Opcode::Call => {
+                instruction.operands[0]
+            }
+            _ => continue,
+        };
+        if !matches!(target, Value::Number(target) if targets.contains(&target)) {
+            continue;
+        }
+        let setup = lines[index.saturating_sub(MAX_SETUP_LEN)..index]
+            .iter()
+            .rev()
+            .take_while(|line| matches!(line.item, Item::Instruction(_)));
+        for previous in setup {
+            if let Item::Instruction(Instruction {
+                opcode: Opcode::Set,
+                operands,
+            }) = &previous.item
+            {
+                if let [Value::Register(0), Value::Number(addr)] = operands.as_slice() {
+                    sites.push((previous.addr, *addr, line.addr));
+                    break;
+                }
+            }
+        }
+    }
+    sites
+}
+
+/// The length-prefixed string at `addr` when every character is printable.
+fn plain(memory: &[u16], addr: usize) -> Option<String> {
+    let len = usize::from(memory[addr]);
+    if len < MIN_PLAIN_LEN {
+        return None;
+    }
+    memory
+        .get(addr + 1..=addr + len)?
+        .iter()
+        .map(|&word| {
+            let c = char::from(u8::try_from(word).ok()?);
+            printable(c).then_some(c)
+        })
+        .collect()
+}
+
+fn printable(c: char) -> bool {
+    c == '\n' || c == ' ' || c.is_ascii_graphic()
+}
+
+/// A VM that runs parts of the program on a copy of its memory.
+struct Sandbox {
+    vm: VM,
+    io: Scripted,
+    start: Snapshot,
+}
+
+impl Sandbox {
+    fn new(memory: &[u16]) -> Result<Sandbox, VmError> {
+        let io = Scripted::new();
+        let vm = VM::boot(io.clone()).load_program(memory.to_vec())?;
+        let start = vm.snapshot();
+        Ok(Sandbox { vm, io, start })
+    }
+
+    fn jump(&mut self, addr: usize) {
+        let mut snapshot = self.start.clone();
+        snapshot.pointer = addr;
+        self.vm.restore(&snapshot);
+        self.io.take_output();
+    }
+
+    /// Runs the routine at `entry` until it returns and returns the memory.
+    /// Returning with the empty stack of the sandbox halts the VM.
+    fn decrypt(mut self, entry: usize) -> Result<Vec<u16>, VmError> {
+        self.jump(entry);
+        match self.vm.run_for(MAX_STEPS)? {
+            ExitReason::Halted => Ok(self.vm.memory()[..self.start.memory.len()].to_vec()),
+            ExitReason::StepLimit => Err(VmError {
+                kind: VmErrorKind::Unfinished { steps: MAX_STEPS },
+                pointer: self.vm.pointer(),
+                registers: *self.vm.registers(),
+            }),
+        }
+    }
+
+    /// Runs from `start` until the pointer reaches `end` with an empty
+    /// stack, returning the output or `None` if it never gets there.
+    fn output(&mut self, start: usize, end: usize) -> Option<String> {
+        self.jump(start);
+        for _ in 0..MAX_STEPS {
+            if self.vm.pointer() == end && self.vm.stack().is_empty() {
+                return Some(self.io.take_output());
+            }
+            match self.vm.step() {
+                Ok(event) if event.exit == Some(ExitReason::Halted) => return None,
+                Ok(_) => {}
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary;
+
+    #[test]
+    fn extract_from_the_challenge() {
+        let program = binary::challenge();
+        assert_eq!(find_decryptor(&program), Some(1723));
+        assert_eq!(find_printer(&program), Some(1458));
+
+        let texts = extract(&program).unwrap();
+        let find = |needle: &str| texts.iter().find(|text| text.text.contains(needle));
+        assert_eq!(find("Foothills").map(|text| text.call_site), Some(None));
+        assert!(find("You find yourself standing at the base of an enormous mountain").is_some());
+        assert!(texts
+            .iter()
+            .any(|text| text.call_site.is_some() && text.text.contains("self-test")));
+
+        assert!(texts.iter().all(|text| text.text.chars().all(printable)));
+
+        let mut out = vec![];
+        write_texts(&texts[..2], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "06068: \"test string\"\n06080 @01046: \"self-test FAILED!!\"\n"
+        );
+    }
+
+    #[test]
+    fn decrypt_until_the_routine_returns() {
+        // 0: wmem 5 7; 3: ret; 4: jmp 4
+        let program = [16, 5, 7, 18, 6, 4];
+        assert_eq!(
+            Sandbox::new(&program).unwrap().decrypt(0).unwrap()[..6],
+            [16, 5, 7, 18, 6, 7]
+        );
+        assert_eq!(
+            Sandbox::new(&program).unwrap().decrypt(4).unwrap_err().kind,
+            VmErrorKind::Unfinished { steps: MAX_STEPS }
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Solver for the teleporter confirmation.
+//!
+//! The teleporter calls a recursive routine, a variant of the Ackermann
+//! function where the eighth register takes the place of the constant 1:
+//!
+//! ```text
+//! f(0, n) = n + 1
+//! f(m, 0) = f(m - 1, r7)
+//! f(m, n) = f(m - 1, f(m, n - 1))
+//! ```
+//!
+//! with every value modulo 32768. Running it on the VM takes forever, but
+//! each row `f(m, _)` only depends on the previous one, so a row of 32768
+//! values at a time evaluates it in a few milliseconds for a given `r7`.
+
+use crate::{
+    disasm,
+    opcode::Opcode,
+    vm::{Instruction, Value, VmError, VM},
+};
+use std::{mem, ops::Range, thread};
+
+const MODULUS: usize = 32768;
+
+/// A call to the confirmation routine and the result the caller expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Confirmation {
+    pub routine: usize,
+    pub call_site: usize,
+    pub r0: usize,
+    pub r1: usize,
+    /// The register that parametrizes the routine, r7 in the challenge.
+    pub register: usize,
+    pub expected: usize,
+}
+
+/// Looks for a call to the confirmation routine, preceded by setting `r0`
+/// and `r1` and followed by comparing `r0` to the expected result.
+pub fn find(memory: &[u16]) -> Option<Confirmation> {
+    let lines = disasm::disassemble(memory, 0);
+    lines.windows(4).find_map(|window| {
+        let instructions: Vec<&Instruction> = window
+            .iter()
+            .filter_map(|line| match &line.item {
+                disasm::Item::Instruction(instruction) => Some(instruction),
+                _ => None,
+            })
+            .collect();
+        let (r0, r1, routine, expected) = match instructions.as_slice() {
+            [set_r0, set_r1, call, eq] => match (
+                set_r0.opcode,
+                set_r0.operands.as_slice(),
+                set_r1.opcode,
+                set_r1.operands.as_slice(),
+                call.opcode,
+                call.operands.as_slice(),
+                eq.opcode,
+                eq.operands.as_slice(),
+            ) {
+                (
+                    Opcode::Set,
+                    [Value::Register(0), Value::Number(r0)],
+                    Opcode::Set,
+                    [Value::Register(1), Value::Number(r1)],
+                    Opcode::Call,
+                    [Value::Number(routine)],
+                    Opcode::Eq,
+                    [_, Value::Register(0), Value::Number(expected)],
+                ) => (*r0, *r1, *routine, *expected),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(Confirmation {
+            routine,
+            call_site: window[2].addr,
+            r0,
+            r1,
+            register: routine_register(memory, routine)?,
+            expected,
+        })
+    })
+}
+
+/// Checks that the routine at `addr` has the shape of the confirmation,
+/// returning the register it is parametrized with.
+fn routine_register(memory: &[u16], addr: usize) -> Option<usize> {
+    (2..8).find(|register| {
+        let expected = [
+            format!("jt r0 {}", addr + 8),
+            "add r0 r1 1".to_string(),
+            "ret".to_string(),
+            format!("jt r1 {}", addr + 21),
+            "add r0 r0 32767".to_string(),
+            format!("set r1 r{}", register),
+            format!("call {}", addr),
+            "ret".to_string(),
+            "push r0".to_string(),
+            "add r1 r1 32767".to_string(),
+            format!("call {}", addr),
+            "set r1 r0".to_string(),
+            "pop r0".to_string(),
+            "add r0 r0 32767".to_string(),
+            format!("call {}", addr),
+            "ret".to_string(),
+        ];
+        let mut pointer = addr;
+        expected.iter().all(|text| {
+            if pointer >= memory.len() {
+                return false;
+            }
+            match disasm::decode(memory, pointer) {
+                Some(instruction) if instruction.to_string() == *text => {
+                    pointer += instruction.opcode.width();
+                    true
+                }
+                _ => false,
+            }
+        })
+    })
+}
+
+/// Evaluates the routine for the given arguments and value of the register.
+pub fn evaluate(r0: usize, r1: usize, register: usize) -> usize {
+    let mut previous: Vec<u16> = (0..MODULUS).map(|n| ((n + 1) % MODULUS) as u16).collect();
+    let mut current = vec![0; MODULUS];
+    for m in 1..=r0 {
+        let len = if m == r0 { r1 + 1 } else { MODULUS };
+        current[0] = previous[register % MODULUS];
+        for n in 1..len {
+            current[n] = previous[current[n - 1] as usize];
+        }
+        mem::swap(&mut previous, &mut current);
+    }
+    previous[r1 % MODULUS].into()
+}
+
+/// Evaluates the routine for every candidate value of the register in
+/// parallel, returning the ones that give the expected result.
+pub fn solve(confirmation: &Confirmation, candidates: Range<usize>) -> Vec<usize> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = candidates.len().div_ceil(threads).max(1);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (candidates.start..candidates.end)
+            .step_by(chunk)
+            .map(|start| {
+                let end = (start + chunk).min(candidates.end);
+                scope.spawn(move || {
+                    (start..end)
+                        .filter(|&value| {
+                            evaluate(confirmation.r0, confirmation.r1, value)
+                                == confirmation.expected
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("solver thread panicked"))
+            .collect()
+    })
+}
+
+/// Sets the register to the solution and replaces the routine with one that
+/// returns the expected result right away.
+pub fn patch(vm: &mut VM, confirmation: &Confirmation, value: usize) -> Result<(), VmError> {
+    let shortcut = [
+        Instruction {
+            opcode: Opcode::Set,
+            operands: vec![Value::Register(0), Value::Number(confirmation.expected)],
+        },
+        Instruction {
+            opcode: Opcode::Ret,
+            operands: vec![],
+        },
+    ];
+    let words = shortcut.iter().flat_map(Instruction::encode);
+    for (addr, word) in (confirmation.routine..).zip(words) {
+        vm.poke(addr, word)?;
+    }
+    vm.poke_register(confirmation.register, value)
+}
+
+/// Like [`patch`], but leaves the memory alone and runs a hook instead of
+/// the routine.
+pub fn hook(vm: &mut VM, confirmation: &Confirmation, value: usize) -> Result<(), VmError> {
+    let expected = confirmation.expected;
+    vm.add_hook(confirmation.routine, move |context| {
+        context.set_register(0, expected);
+        Ok(())
+    });
+    vm.poke_register(confirmation.register, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{binary::challenge, device::Scripted, vm::ExitReason};
+
+    #[test]
+    fn evaluate_small_arguments() {
+        assert_eq!(evaluate(0, 5, 1), 6);
+        assert_eq!(evaluate(1, 2, 1), 4);
+        assert_eq!(evaluate(2, 3, 1), 9);
+        assert_eq!(evaluate(3, 2, 1), 29);
+    }
+
+    #[test]
+    fn finds_the_confirmation() {
+        assert_eq!(
+            find(&challenge()),
+            Some(Confirmation {
+                routine: 6027,
+                call_site: 5489,
+                r0: 4,
+                r1: 1,
+                register: 7,
+                expected: 6,
+            })
+        );
+    }
+
+    #[test]
+    fn solves_and_patches() {
+        let confirmation = find(&challenge()).unwrap();
+        assert_eq!(solve(&confirmation, 25700..25800), vec![25734]);
+
+        // 0: set r0 4; 3: set r1 1; 6: call 6027; 8: halt
+        let mut program = challenge();
+        program[..9].copy_from_slice(&[1, 32768, 4, 1, 32769, 1, 17, 6027, 0]);
+        let mut vm = VM::boot(Scripted::new()).load_program(program).unwrap();
+        patch(&mut vm, &confirmation, 25734).unwrap();
+
+        vm.run().unwrap();
+        assert_eq!(vm.registers()[0], 6);
+        assert_eq!(vm.registers()[7], 25734);
+    }
+
+    #[test]
+    fn hooks_the_routine() {
+        let confirmation = find(&challenge()).unwrap();
+        let mut program = challenge();
+        program[..9].copy_from_slice(&[1, 32768, 4, 1, 32769, 1, 17, 6027, 0]);
+        let mut vm = VM::boot(Scripted::new()).load_program(program).unwrap();
+        hook(&mut vm, &confirmation, 25734).unwrap();
+
+        assert_eq!(vm.run_for(4), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers()[0], 6);
+        assert_eq!(vm.memory()[6027], 7);
+    }
+}
@@ -0,0 +1,275 @@
+//! Structured execution traces, one record per executed instruction.
+//!
+//! JSON Lines records look like
+//!
+//! ```text
+//! {"step":0,"pointer":0,"opcode":"add","operands":["r0","r1",4],"values":[0,61,4],
+//!  "effects":[{"type":"register","register":0,"old":0,"new":65}]}
+//! ```
+//!
+//! The binary format starts with the `SYNTRACE` magic and a little-endian u16
+//! version, followed by the records with every number little-endian:
+//!
+//! - u64 step, u16 pointer, u8 opcode
+//! - u8 number of operands, then for each the u16 encoded word and u16 value
+//! - u8 number of effects, then for each a u8 tag and its payload:
+//!   0 register (u8 register, u16 old, u16 new), 1 memory (u16 address,
+//!   u16 old, u16 new), 2 push (u16), 3 pop (u16), 4 output (u16),
+//!   5 input (u16)
+
+use crate::{
+    json,
+    opcode::Opcode,
+    vm::{Effect, StepEvent, Value},
+};
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+const MAGIC: &[u8; 8] = b"SYNTRACE";
+const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Binary,
+}
+
+/// Selects the instructions to record, everything is recorded by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<usize>>,
+    pub opcodes: Option<HashSet<Opcode>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, event: &StepEvent) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|range| range.contains(&event.pointer))
+            && self
+                .opcodes
+                .as_ref()
+                .is_none_or(|opcodes| opcodes.contains(&event.instruction.opcode))
+    }
+}
+
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    started: bool,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W, format: TraceFormat, filter: TraceFilter) -> Tracer<W> {
+        Tracer {
+            writer,
+            format,
+            filter,
+            started: false,
+        }
+    }
+
+    pub fn record(&mut self, event: &StepEvent) -> io::Result<()> {
+        if !self.filter.matches(event) {
+            return Ok(());
+        }
+        match self.format {
+            TraceFormat::JsonLines => self.write_json(event),
+            TraceFormat::Binary => self.write_binary(event),
+        }
+    }
+
+    /// Flushes the writer, writing the header of a binary trace first when
+    /// no instruction was recorded, so an empty trace can still be identified.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.format == TraceFormat::Binary {
+            self.write_header()?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.writer.write_all(MAGIC)?;
+            self.writer.write_all(&VERSION.to_le_bytes())?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    fn write_json(&mut self, event: &StepEvent) -> io::Result<()> {
+        let operands: Vec<String> = event
+            .instruction
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                Value::Number(n) => n.to_string(),
+                Value::Register(r) => format!("\"r{}\"", r),
+            })
+            .collect();
+        let values: Vec<String> = event.values.iter().map(usize::to_string).collect();
+        let effects: Vec<String> = event.effects.iter().map(effect_json).collect();
+
+        writeln!(
+            self.writer,
+            "{{\"step\":{},\"pointer\":{},\"opcode\":\"{}\",\"operands\":[{}],\"values\":[{}],\"effects\":[{}]}}",
+            event.index,
+            event.pointer,
+            event.instruction.opcode,
+            operands.join(","),
+            values.join(","),
+            effects.join(",")
+        )
+    }
+
+    fn write_binary(&mut self, event: &StepEvent) -> io::Result<()> {
+        self.write_header()?;
+
+        let mut record = Vec::with_capacity(32);
+        record.extend_from_slice(&event.index.to_le_bytes());
+        record.extend_from_slice(&(event.pointer as u16).to_le_bytes());
+        record.push(event.instruction.opcode.code() as u8);
+        record.push(event.instruction.operands.len() as u8);
+        for (operand, value) in event.instruction.operands.iter().zip(&event.values) {
+            record.extend_from_slice(&operand.encode().to_le_bytes());
+            record.extend_from_slice(&(*value as u16).to_le_bytes());
+        }
+        record.push(event.effects.len() as u8);
+        for effect in &event.effects {
+            let words: &[usize] = match effect {
+                Effect::RegisterWrite { register, old, new } => {
+                    record.push(0);
+                    record.push(*register as u8);
+                    &[*old, *new]
+                }
+                Effect::MemoryWrite { addr, old, new } => {
+                    record.push(1);
+                    &[*addr, *old, *new]
+                }
+                Effect::StackPush(value) => {
+                    record.push(2);
+                    &[*value]
+                }
+                Effect::StackPop(value) => {
+                    record.push(3);
+                    &[*value]
+                }
+                Effect::Output(c) => {
+                    record.push(4);
+                    &[*c as usize]
+                }
+                Effect::Input(value) => {
+                    record.push(5);
+                    &[*value]
+                }
+            };
+            for word in words {
+                record.extend_from_slice(&(*word as u16).to_le_bytes());
+            }
+        }
+        self.writer.write_all(&record)
+    }
+}
+
+fn effect_json(effect: &Effect) -> String {
+    match effect {
+        Effect::RegisterWrite { register, old, new } => format!(
+            "{{\"type\":\"register\",\"register\":{},\"old\":{},\"new\":{}}}",
+            register, old, new
+        ),
+        Effect::MemoryWrite { addr, old, new } => format!(
+            "{{\"type\":\"memory\",\"addr\":{},\"old\":{},\"new\":{}}}",
+            addr, old, new
+        ),
+        Effect::StackPush(value) => format!("{{\"type\":\"push\",\"value\":{}}}", value),
+        Effect::StackPop(value) => format!("{{\"type\":\"pop\",\"value\":{}}}", value),
+        Effect::Output(c) => format!(
+            "{{\"type\":\"output\",\"char\":{}}}",
+            json::string(&c.to_string())
+        ),
+        Effect::Input(value) => format!("{{\"type\":\"input\",\"value\":{}}}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{device::Scripted, vm::VM};
+
+    fn trace(format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+        let program = vec![9, 32768, 32769, 4, 19, 32768, 2, 34, 0];
+        let mut vm = VM::boot(Scripted::new()).load_program(program).unwrap();
+        vm.poke_register(1, 30).unwrap();
+
+        let mut tracer = Tracer::new(vec![], format, filter);
+        for _ in 0..4 {
+            tracer.record(&vm.step().unwrap()).unwrap();
+        }
+        tracer.flush().unwrap();
+        tracer.writer
+    }
+
+    #[test]
+    fn json_lines() {
+        let output =
+            String::from_utf8(trace(TraceFormat::JsonLines, TraceFilter::default())).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(
+            lines,
+            vec![
+                "{\"step\":0,\"pointer\":0,\"opcode\":\"add\",\"operands\":[\"r0\",\"r1\",4],\"values\":[0,30,4],\"effects\":[{\"type\":\"register\",\"register\":0,\"old\":0,\"new\":34}]}",
+                "{\"step\":1,\"pointer\":4,\"opcode\":\"out\",\"operands\":[\"r0\"],\"values\":[34],\"effects\":[{\"type\":\"output\",\"char\":\"\\\"\"}]}",
+                "{\"step\":2,\"pointer\":6,\"opcode\":\"push\",\"operands\":[34],\"values\":[34],\"effects\":[{\"type\":\"push\",\"value\":34}]}",
+                "{\"step\":3,\"pointer\":8,\"opcode\":\"halt\",\"operands\":[],\"values\":[],\"effects\":[]}",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let filter = TraceFilter {
+            addresses: Some(4..=8),
+            opcodes: Some(
+                vec![Opcode::Push, Opcode::Add, Opcode::Halt]
+                    .into_iter()
+                    .collect(),
+            ),
+        };
+        let output = String::from_utf8(trace(TraceFormat::JsonLines, filter)).unwrap();
+        let steps: Vec<&str> = output.lines().map(|line| &line[..9]).collect();
+
+        assert_eq!(steps, vec!["{\"step\":2", "{\"step\":3"]);
+    }
+
+    #[test]
+    fn binary() {
+        let filter = TraceFilter {
+            addresses: Some(6..=6),
+            opcodes: None,
+        };
+        let output = trace(TraceFormat::Binary, filter);
+
+        assert_eq!(&output[..8], MAGIC);
+        assert_eq!(
+            &output[10..],
+            &[2, 0, 0, 0, 0, 0, 0, 0, 6, 0, 2, 1, 34, 0, 34, 0, 1, 2, 34, 0]
+        );
+    }
+
+    #[test]
+    fn empty_binary_trace() {
+        let filter = TraceFilter {
+            addresses: Some(100..=200),
+            opcodes: None,
+        };
+        let output = trace(TraceFormat::Binary, filter);
+
+        assert_eq!(&output[..8], MAGIC);
+        assert_eq!(output[8..], VERSION.to_le_bytes());
+    }
+}
@@ -0,0 +1,254 @@
+//! Solver for the orb puzzle in front of the vault.
+//!
+//! The rooms before the vault form a grid of numbers and operators. The orb
+//! starts in the bottom left room with the weight of its number, every step
+//! onto an operator and then a number applies that operation to the weight,
+//! and the vault door in the top right room only opens for a weight of 30.
+//! The orb evaporates when it returns to the first room or reaches the door
+//! with the wrong weight.
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+};
+
+/// The grid of the challenge, north at the top.
+pub const CHALLENGE: &str = "\
+*  8  -  1
+4  *  11 *
++  4  -  18
+22 -  9  *";
+
+/// The weight that opens the vault door.
+pub const TARGET: i64 = 30;
+
+/// Weights are kept below this bound, the search gives up on heavier orbs.
+const MAX_WEIGHT: i64 = 32768;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cell {
+    Number(i64),
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Direction {
+    const ALL: [Direction; 4] = [
+        Direction::North,
+        Direction::East,
+        Direction::South,
+        Direction::West,
+    ];
+
+    fn offset(self) -> (isize, isize) {
+        match self {
+            Direction::North => (-1, 0),
+            Direction::East => (0, 1),
+            Direction::South => (1, 0),
+            Direction::West => (0, -1),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Direction::North => "north",
+            Direction::East => "east",
+            Direction::South => "south",
+            Direction::West => "west",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A grid with the orb in the bottom left and the door in the top right.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    cells: Vec<Vec<Cell>>,
+}
+
+/// Where the orb is, what it weighs and the operator it last stepped on.
+type State = ((usize, usize), i64, Option<Cell>);
+
+impl Grid {
+    /// Parses rows of whitespace separated numbers and `+`, `-` or `*`
+    /// operators, north first.
+    pub fn parse(text: &str) -> Result<Grid, String> {
+        let cells: Vec<Vec<Cell>> = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().map(cell).collect())
+            .collect::<Result<_, _>>()?;
+
+        let width = cells.first().map_or(0, Vec::len);
+        if width == 0 || cells.iter().any(|row| row.len() != width) {
+            return Err("the rows of the grid must have the same length".to_string());
+        }
+        let grid = Grid { cells };
+        match (grid.cell(grid.start()), grid.cell(grid.door())) {
+            (Cell::Number(_), Cell::Number(_)) => Ok(grid),
+            _ => Err("the first room and the door must hold numbers".to_string()),
+        }
+    }
+
+    fn start(&self) -> (usize, usize) {
+        (self.cells.len() - 1, 0)
+    }
+
+    fn door(&self) -> (usize, usize) {
+        (0, self.cells[0].len() - 1)
+    }
+
+    fn cell(&self, (row, col): (usize, usize)) -> Cell {
+        self.cells[row][col]
+    }
+
+    fn neighbour(
+        &self,
+        (row, col): (usize, usize),
+        direction: Direction,
+    ) -> Option<(usize, usize)> {
+        let (dr, dc) = direction.offset();
+        let row = row
+            .checked_add_signed(dr)
+            .filter(|&row| row < self.cells.len())?;
+        let col = col
+            .checked_add_signed(dc)
+            .filter(|&col| col < self.cells[0].len())?;
+        Some((row, col))
+    }
+
+    /// Moves the orb one room, `None` when it evaporates or gets too heavy.
+    fn step(&self, (position, weight, operator): State, direction: Direction) -> Option<State> {
+        let next = self.neighbour(position, direction)?;
+        if next == self.start() {
+            return None;
+        }
+        let weight = match (self.cell(next), operator) {
+            (Cell::Number(n), Some(Cell::Add)) => weight + n,
+            (Cell::Number(n), Some(Cell::Sub)) => weight - n,
+            (Cell::Number(n), Some(Cell::Mul)) => weight * n,
+            (Cell::Number(_), _) => weight,
+            (op, _) => return Some((next, weight, Some(op))),
+        };
+        if !(0..MAX_WEIGHT).contains(&weight) || (next == self.door() && weight != TARGET) {
+            return None;
+        }
+        Some((next, weight, None))
+    }
+
+    /// Finds the shortest walk that brings the orb to the door with the
+    /// target weight.
+    pub fn solve(&self) -> Option<Vec<Direction>> {
+        let weight = match self.cell(self.start()) {
+            Cell::Number(n) => n,
+            _ => return None,
+        };
+        let first: State = (self.start(), weight, None);
+        let mut seen: HashSet<State> = HashSet::from([first]);
+        let mut queue: VecDeque<(State, Vec<Direction>)> = VecDeque::from([(first, vec![])]);
+
+        while let Some((state, path)) = queue.pop_front() {
+            for direction in Direction::ALL {
+                let next = match self.step(state, direction) {
+                    Some(next) => next,
+                    None => continue,
+                };
+                let mut path = path.clone();
+                path.push(direction);
+                if next.0 == self.door() {
+                    return Some(path);
+                }
+                if seen.insert(next) {
+                    queue.push_back((next, path));
+                }
+            }
+        }
+        None
+    }
+
+    /// The weight of the orb after the walk, `None` when it evaporates on
+    /// the way.
+    pub fn walk(&self, directions: &[Direction]) -> Option<i64> {
+        let weight = match self.cell(self.start()) {
+            Cell::Number(n) => n,
+            _ => return None,
+        };
+        directions
+            .iter()
+            .try_fold((self.start(), weight, None), |state, &direction| {
+                self.step(state, direction)
+            })
+            .map(|(_, weight, _)| weight)
+    }
+}
+
+fn cell(token: &str) -> Result<Cell, String> {
+    match token {
+        "+" => Ok(Cell::Add),
+        "-" => Ok(Cell::Sub),
+        "*" => Ok(Cell::Mul),
+        _ => token
+            .parse()
+            .map(Cell::Number)
+            .map_err(|_| format!("invalid room: {}", token)),
+    }
+}
+
+/// The commands that walk the orb to the vault door.
+pub fn commands(directions: &[Direction]) -> Vec<String> {
+    directions
+        .iter()
+        .map(|direction| format!("go {}", direction))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_grids() {
+        let grid = Grid::parse(CHALLENGE).unwrap();
+        assert_eq!(grid.cell(grid.start()), Cell::Number(22));
+        assert_eq!(grid.cell(grid.door()), Cell::Number(1));
+        assert_eq!(grid.cell((1, 2)), Cell::Number(11));
+        assert!(Grid::parse("1 +\n2").is_err());
+        assert!(Grid::parse("1 /\n2 3").is_err());
+        assert!(Grid::parse("1 +\n2 3").is_err());
+    }
+
+    #[test]
+    fn solve_the_vault() {
+        let grid = Grid::parse(CHALLENGE).unwrap();
+        let path = grid.solve().unwrap();
+        assert_eq!(path.len(), 12);
+        assert_eq!(grid.walk(&path), Some(TARGET));
+        assert_eq!(
+            commands(&path[..2]),
+            vec!["go north".to_string(), "go east".to_string()]
+        );
+
+        // back to the start, off the grid, and into the door too light
+        use Direction::*;
+        assert_eq!(grid.walk(&[North, South]), None);
+        assert_eq!(grid.walk(&[West]), None);
+        assert_eq!(grid.walk(&[East, East, North, North, East, North]), None);
+    }
+
+    #[test]
+    fn unsolvable() {
+        let grid = Grid::parse("1 2\n3 4").unwrap();
+        assert_eq!(grid.solve(), None);
+    }
+}
@@ -1,123 +1,524 @@
-use core::panic;
-use std::{char, convert::TryInto, io, usize};
+use crate::{
+    binary,
+    device::IoDevice,
+    hook::{HookContext, Hooks},
+    meta::MetaCommand,
+    opcode::Opcode,
+    snapshot::Snapshot,
+    watch::{WatchHit, WatchTarget, Watchpoints},
+};
+use std::{char, convert::TryInto, error, fmt, io, path::PathBuf};
 use Value::{Number, Register};
 
+/// Number of words addressable with the 15-bit address space.
+pub const MEMORY_SIZE: usize = 32768;
+
 #[derive(Debug)]
 pub struct VM {
-    memory: Vec<usize>,
+    memory: Vec<u16>,
     registers: [usize; 8],
     stack: Vec<usize>,
     pointer: usize,
     input_buffer: Vec<usize>,
+    steps: u64,
+    effects: Vec<Effect>,
+    io: Box<dyn IoDevice>,
+    state: VmState,
+    snapshot_dir: PathBuf,
+    watchpoints: Watchpoints,
+    hooks: Hooks,
+}
+
+/// Whether the VM can execute more instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmState {
+    Running,
+    Halted,
+}
+
+/// Everything that happened while executing a single instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepEvent {
+    /// Number of instructions executed before this one.
+    pub index: u64,
+    pub pointer: usize,
+    pub instruction: Instruction,
+    /// Values of the operands before the instruction was executed, registers
+    /// resolved to their contents.
+    pub values: Vec<usize>,
+    pub effects: Vec<Effect>,
+    /// Pointer of the next instruction to be executed.
+    pub next_pointer: usize,
+    pub exit: Option<ExitReason>,
+    pub watch_hits: Vec<WatchHit>,
+}
+
+impl StepEvent {
+    /// The characters written by the instruction.
+    pub fn output(&self) -> impl Iterator<Item = char> + '_ {
+        self.effects.iter().filter_map(|effect| match effect {
+            Effect::Output(c) => Some(*c),
+            _ => None,
+        })
+    }
+}
+
+/// A decoded instruction with its raw operands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub operands: Vec<Value>,
+}
+
+impl Instruction {
+    /// The words the instruction is stored as in memory.
+    pub fn encode(&self) -> Vec<u16> {
+        std::iter::once(self.opcode.code() as u16)
+            .chain(self.operands.iter().map(|operand| operand.encode()))
+            .collect()
+    }
+}
+
+/// A side effect of an instruction on the state of the VM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    RegisterWrite {
+        register: usize,
+        old: usize,
+        new: usize,
+    },
+    MemoryWrite {
+        addr: usize,
+        old: usize,
+        new: usize,
+    },
+    StackPush(usize),
+    StackPop(usize),
+    Output(char),
+    Input(usize),
+}
+
+/// Describes why the VM stopped executing without an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    Halted,
+    StepLimit,
+}
+
+/// An error raised while executing the program, along with the state of the
+/// VM at the instruction that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmError {
+    pub kind: VmErrorKind,
+    pub pointer: usize,
+    pub registers: [usize; 8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmErrorKind {
+    InvalidOpcode { addr: usize, opcode: usize },
+    InvalidValue { addr: usize, raw: usize },
+    ExpectedRegister { addr: usize, raw: usize },
+    StackUnderflow,
+    OutOfBoundsAddress { addr: usize },
+    InvalidChar { code: usize },
+    InputExhausted,
+    ProgramTooLarge { len: usize },
+    DivisionByZero { addr: usize },
+    InvalidRegister { register: usize },
+    WordOutOfRange { value: usize },
+    Unfinished { steps: u64 },
+    AlreadyHalted,
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {} (registers: {:?})",
+            self.kind, self.pointer, self.registers
+        )
+    }
+}
+
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmErrorKind::InvalidOpcode { addr, opcode } => {
+                write!(f, "invalid opcode {} at {}", opcode, addr)
+            }
+            VmErrorKind::InvalidValue { addr, raw } => {
+                write!(f, "invalid value {} at {}", raw, addr)
+            }
+            VmErrorKind::ExpectedRegister { addr, raw } => {
+                write!(f, "expected a register but got {} at {}", raw, addr)
+            }
+            VmErrorKind::StackUnderflow => write!(f, "pop from an empty stack"),
+            VmErrorKind::OutOfBoundsAddress { addr } => {
+                write!(f, "address {} is out of bounds", addr)
+            }
+            VmErrorKind::InvalidChar { code } => write!(f, "invalid character code {}", code),
+            VmErrorKind::InputExhausted => write!(f, "no more input available"),
+            VmErrorKind::ProgramTooLarge { len } => write!(
+                f,
+                "program of {} words does not fit into {} words of memory",
+                len, MEMORY_SIZE
+            ),
+            VmErrorKind::DivisionByZero { addr } => write!(f, "division by zero at {}", addr),
+            VmErrorKind::InvalidRegister { register } => write!(f, "invalid register {}", register),
+            VmErrorKind::WordOutOfRange { value } => {
+                write!(f, "value {} does not fit into 15 bits", value)
+            }
+            VmErrorKind::Unfinished { steps } => {
+                write!(f, "the routine did not return within {} steps", steps)
+            }
+            VmErrorKind::AlreadyHalted => write!(f, "the program has already halted"),
+            VmErrorKind::Io(kind) => write!(f, "i/o error: {:?}", kind),
+        }
+    }
 }
 
+impl error::Error for VmError {}
+
 impl VM {
-    pub fn boot() -> VM {
+    pub fn boot<D: IoDevice + 'static>(io: D) -> VM {
         VM {
-            memory: vec![],
+            memory: vec![0; MEMORY_SIZE],
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
             stack: vec![],
             pointer: 0,
             input_buffer: vec![],
+            steps: 0,
+            effects: vec![],
+            io: Box::new(io),
+            state: VmState::Running,
+            snapshot_dir: PathBuf::from("snapshots"),
+            watchpoints: Watchpoints::default(),
+            hooks: Hooks::default(),
         }
     }
 
-    pub fn load_program(mut self, program: Vec<usize>) -> Self {
+    /// Sets the directory used by the `!save` and `!load` meta-commands.
+    pub fn set_snapshot_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.snapshot_dir = dir.into();
+    }
+
+    /// Captures everything needed to resume the program later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory.clone(),
+            registers: self.registers,
+            stack: self.stack.clone(),
+            pointer: self.pointer,
+            input_buffer: self.input_buffer.clone(),
+        }
+    }
+
+    /// Replaces the state of the VM with a previously taken snapshot.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory = snapshot.memory.clone();
+        self.memory.resize(MEMORY_SIZE, 0);
+        self.registers = snapshot.registers;
+        self.stack = snapshot.stack.clone();
+        self.pointer = snapshot.pointer;
+        self.input_buffer = snapshot.input_buffer.clone();
+        self.state = VmState::Running;
+    }
+
+    /// Loads the program into the memory starting at address 0, the rest of
+    /// the memory is zeroed.
+    pub fn load_program(mut self, program: Vec<u16>) -> Result<Self, VmError> {
+        if program.len() > MEMORY_SIZE {
+            return Err(self.error(VmErrorKind::ProgramTooLarge { len: program.len() }));
+        }
         self.memory = program;
-        self
+        self.memory.resize(MEMORY_SIZE, 0);
+        Ok(self)
     }
 
-    pub fn run(mut self) -> Self {
+    /// Runs the program until it halts or fails. The VM keeps its final state
+    /// so it can be inspected after the program finished.
+    pub fn run(&mut self) -> Result<ExitReason, VmError> {
+        if self.state == VmState::Halted {
+            return Ok(ExitReason::Halted);
+        }
         log::debug!(
             "running the program loaded into the memory from {}",
             self.pointer
         );
-        while self.read_value_at_pointer() != 0 {
-            let op_code = self.read_value_at_pointer();
-            match op_code {
-                0 => self.halt(),
-                1 => self.set(),
-                2 => self.push(),
-                3 => self.pop(),
-                4 => self.eq(),
-                5 => self.gt(),
-                6 => self.jmp(),
-                7 => self.jt(),
-                8 => self.jf(),
-                9 => self.add(),
-                10 => self.mult(),
-                11 => self.r#mod(),
-                12 => self.and(),
-                13 => self.or(),
-                14 => self.not(),
-                15 => self.rmem(),
-                16 => self.wmem(),
-                17 => self.call(),
-                18 => self.ret(),
-                19 => self.out(),
-                20 => self.r#in(),
-                21 => self.noop(),
-                _ => self.unimplemented(),
+        loop {
+            if let Some(reason) = self.step()?.exit {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Runs the program until it halts, fails or executes `max_steps`
+    /// instructions.
+    pub fn run_for(&mut self, max_steps: u64) -> Result<ExitReason, VmError> {
+        if self.state == VmState::Halted {
+            return Ok(ExitReason::Halted);
+        }
+        for _ in 0..max_steps {
+            if let Some(reason) = self.step()?.exit {
+                return Ok(reason);
             }
         }
-        self
+        Ok(ExitReason::StepLimit)
+    }
+
+    /// Decodes and executes exactly one instruction at the current pointer.
+    pub fn step(&mut self) -> Result<StepEvent, VmError> {
+        if self.state == VmState::Halted {
+            return Err(self.error(VmErrorKind::AlreadyHalted));
+        }
+        let pointer = self.pointer;
+        let instruction = self.decode(pointer)?;
+        let values = instruction
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                Number(n) => *n,
+                Register(r) => self.registers[*r],
+            })
+            .collect();
+        self.effects.clear();
+        self.watchpoints.take_hits();
+
+        let mut exit = None;
+        match instruction.opcode {
+            Opcode::Halt => exit = Some(self.halt()),
+            Opcode::Set => self.set()?,
+            Opcode::Push => self.push()?,
+            Opcode::Pop => self.pop()?,
+            Opcode::Eq => self.eq()?,
+            Opcode::Gt => self.gt()?,
+            Opcode::Jmp => self.jmp()?,
+            Opcode::Jt => self.jt()?,
+            Opcode::Jf => self.jf()?,
+            Opcode::Add => self.add()?,
+            Opcode::Mult => self.mult()?,
+            Opcode::Mod => self.r#mod()?,
+            Opcode::And => self.and()?,
+            Opcode::Or => self.or()?,
+            Opcode::Not => self.not()?,
+            Opcode::Rmem => self.rmem()?,
+            Opcode::Wmem => self.wmem()?,
+            Opcode::Call => self.call()?,
+            Opcode::Ret => exit = self.ret()?,
+            Opcode::Out => self.out()?,
+            Opcode::In => self.r#in()?,
+            Opcode::Noop => self.noop(),
+        }
+        if exit == Some(ExitReason::Halted) {
+            self.state = VmState::Halted;
+        }
+
+        let event = StepEvent {
+            index: self.steps,
+            pointer,
+            instruction,
+            values,
+            effects: self.effects.drain(..).collect(),
+            next_pointer: self.pointer,
+            exit,
+            watch_hits: self.watchpoints.take_hits(),
+        };
+        self.steps += 1;
+        Ok(event)
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn state(&self) -> VmState {
+        self.state
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn registers(&self) -> &[usize; 8] {
+        &self.registers
+    }
+
+    pub fn memory(&self) -> &[u16] {
+        &self.memory
+    }
+
+    pub fn stack(&self) -> &[usize] {
+        &self.stack
+    }
+
+    pub fn watchpoints(&self) -> &Watchpoints {
+        &self.watchpoints
+    }
+
+    pub fn watchpoints_mut(&mut self) -> &mut Watchpoints {
+        &mut self.watchpoints
+    }
+
+    /// Runs `hook` instead of the subroutine at `addr` whenever it is called,
+    /// replacing any previous hook at the same address.
+    pub fn add_hook<F>(&mut self, addr: usize, hook: F)
+    where
+        F: FnMut(&mut HookContext) -> Result<(), VmError> + 'static,
+    {
+        self.hooks.insert(addr, Box::new(hook));
+    }
+
+    pub fn remove_hook(&mut self, addr: usize) -> bool {
+        self.hooks.remove(addr)
+    }
+
+    pub fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    /// Overwrites a register outside of the normal execution of the program.
+    pub fn poke_register(&mut self, register: usize, value: usize) -> Result<(), VmError> {
+        if register >= self.registers.len() {
+            return Err(self.error(VmErrorKind::InvalidRegister { register }));
+        }
+        if value >= MEMORY_SIZE {
+            return Err(self.error(VmErrorKind::WordOutOfRange { value }));
+        }
+        self.registers[register] = value;
+        Ok(())
+    }
+
+    /// Overwrites a word of the memory outside of the normal execution of the
+    /// program.
+    pub fn poke(&mut self, address: usize, word: u16) -> Result<(), VmError> {
+        self.check_address(address)?;
+        self.memory[address] = word;
+        Ok(())
+    }
+
+    /// Writes the whole memory in the same 16-bit little-endian format the
+    /// programs are loaded from.
+    pub fn dump_memory<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        binary::write_binary(writer, &self.memory)
+    }
+
+    /// Replaces the device the program reads from and writes to, returning
+    /// the previous one.
+    pub fn replace_io(&mut self, io: Box<dyn IoDevice>) -> Box<dyn IoDevice> {
+        std::mem::replace(&mut self.io, io)
+    }
+
+    /// Queues text for the program, it is read after the input that is
+    /// already buffered and before anything from the device.
+    pub fn feed_input(&mut self, text: &str) {
+        let mut input: Vec<usize> = text.chars().rev().map(|c| c as usize).collect();
+        input.append(&mut self.input_buffer);
+        self.input_buffer = input;
+    }
+
+    /// Reverts the effects of the last executed instruction, moving the
+    /// pointer back to it. Consumed input is pushed back to the input buffer.
+    pub(crate) fn undo(&mut self, pointer: usize, effects: &[Effect]) {
+        for effect in effects.iter().rev() {
+            match *effect {
+                Effect::RegisterWrite { register, old, .. } => self.registers[register] = old,
+                Effect::MemoryWrite { addr, old, .. } => self.memory[addr] = old as u16,
+                Effect::StackPush(_) => {
+                    self.stack.pop();
+                }
+                Effect::StackPop(value) => self.stack.push(value),
+                Effect::Output(_) => {}
+                Effect::Input(value) => self.input_buffer.push(value),
+            }
+        }
+        self.pointer = pointer;
+        self.steps -= 1;
+        self.state = VmState::Running;
+    }
+
+    /// The pending input, the next character to be read is the last one.
+    pub(crate) fn input_buffer(&self) -> &[usize] {
+        &self.input_buffer
+    }
+
+    pub(crate) fn set_steps(&mut self, steps: u64) {
+        self.steps = steps;
+    }
+
+    /// Decodes the instruction at the given address without executing it.
+    pub fn decode(&self, pointer: usize) -> Result<Instruction, VmError> {
+        let op_code = self.read_memory(pointer)?;
+        let opcode =
+            Opcode::from_code(op_code).ok_or_else(|| self.unimplemented(pointer, op_code))?;
+        let operands = (1..opcode.width())
+            .map(|offset| self.get_value(pointer + offset))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Instruction { opcode, operands })
     }
 
     fn log_opcode(&self, name: &str) {
         log::debug!(
-            "found instruction {} (opcode {}) at {}",
+            "found instruction {} (opcode {:?}) at {}",
             name,
-            self.read_value_at_pointer(),
+            self.memory.get(self.pointer),
             self.pointer
         )
     }
 
-    fn halt(&mut self) {
-        panic!("halt!")
+    fn halt(&mut self) -> ExitReason {
+        self.log_opcode("halt");
+        ExitReason::Halted
     }
 
-    fn set(&mut self) {
+    fn set(&mut self) -> Result<(), VmError> {
         self.log_opcode("set");
 
-        let reg = self.get_register(self.pointer + 1);
+        let reg = self.get_register(self.pointer + 1)?;
 
-        let value = self.read_value(self.pointer + 2);
+        let value = self.read_value(self.pointer + 2)?;
 
         log::debug!("\tregisters before: {:?}", self.registers);
         log::debug!("\tsetting register {} to {}", reg, value);
 
-        self.registers[reg] = value;
+        self.set_register(reg, value);
         log::debug!("\tregisters after : {:?}", self.registers);
 
-        self.pointer += 3
+        self.pointer += 3;
+        Ok(())
     }
 
-    fn push(&mut self) {
+    fn push(&mut self) -> Result<(), VmError> {
         self.log_opcode("push");
 
-        let value = self.read_value(self.pointer + 1);
-        self.stack.push(value);
+        let value = self.read_value(self.pointer + 1)?;
+        self.push_stack(value);
 
         self.pointer += 2;
+        Ok(())
     }
 
-    fn pop(&mut self) {
+    fn pop(&mut self) -> Result<(), VmError> {
         self.log_opcode("pop");
 
-        let register = self.get_register(self.pointer + 1);
+        let register = self.get_register(self.pointer + 1)?;
 
-        let value = self.stack.pop().unwrap();
-        self.registers[register] = value;
+        let value = self.pop_stack()?;
+        self.set_register(register, value);
 
         self.pointer += 2;
+        Ok(())
     }
 
-    fn eq(&mut self) {
+    fn eq(&mut self) -> Result<(), VmError> {
         self.log_opcode("eq");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = if a == b { 1 } else { 0 };
 
         log::debug!(
@@ -128,16 +529,17 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn gt(&mut self) {
+    fn gt(&mut self) -> Result<(), VmError> {
         self.log_opcode("gt");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = if a > b { 1 } else { 0 };
 
         log::debug!(
@@ -148,50 +550,54 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn jmp(&mut self) {
+    fn jmp(&mut self) -> Result<(), VmError> {
         self.log_opcode("jmp");
 
-        let jump_to = self.read_value(self.pointer + 1);
+        let jump_to = self.read_value(self.pointer + 1)?;
         log::debug!("\tjumping to {}", jump_to);
         self.pointer = jump_to;
+        Ok(())
     }
 
-    fn jt(&mut self) {
+    fn jt(&mut self) -> Result<(), VmError> {
         self.log_opcode("jt");
 
-        if self.read_value(self.pointer + 1) != 0 {
-            let jump_to = self.read_value(self.pointer + 2);
+        if self.read_value(self.pointer + 1)? != 0 {
+            let jump_to = self.read_value(self.pointer + 2)?;
             log::debug!("\tjumping to {}", jump_to);
             self.pointer = jump_to
         } else {
             log::debug!("\tnot jumping, moving to next");
             self.pointer += 3;
         }
+        Ok(())
     }
 
-    fn jf(&mut self) {
+    fn jf(&mut self) -> Result<(), VmError> {
         self.log_opcode("jf");
 
-        if self.read_value(self.pointer + 1) == 0 {
-            let jump_to = self.read_value(self.pointer + 2);
+        if self.read_value(self.pointer + 1)? == 0 {
+            let jump_to = self.read_value(self.pointer + 2)?;
             log::debug!("\tjumping to {}", jump_to);
             self.pointer = jump_to;
         } else {
             log::debug!("\tnot jumping, moving to next");
             self.pointer += 3;
         }
+        Ok(())
     }
 
-    fn add(&mut self) {
+    fn add(&mut self) -> Result<(), VmError> {
         self.log_opcode("add");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = modulo(a + b);
 
         log::debug!(
@@ -202,16 +608,17 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn mult(&mut self) {
+    fn mult(&mut self) -> Result<(), VmError> {
         self.log_opcode("mult");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = modulo(a * b);
 
         log::debug!(
@@ -222,16 +629,20 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn r#mod(&mut self) {
+    fn r#mod(&mut self) -> Result<(), VmError> {
         self.log_opcode("mod");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
+        if b == 0 {
+            return Err(self.error(VmErrorKind::DivisionByZero { addr: self.pointer }));
+        }
         let value = a % b;
 
         log::debug!(
@@ -242,16 +653,17 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn and(&mut self) {
+    fn and(&mut self) -> Result<(), VmError> {
         self.log_opcode("and");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = modulo(a & b);
 
         log::debug!(
@@ -262,16 +674,17 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn or(&mut self) {
+    fn or(&mut self) -> Result<(), VmError> {
         self.log_opcode("or");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
-        let b = self.read_value(self.pointer + 3);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
+        let b = self.read_value(self.pointer + 3)?;
         let value = modulo(a | b);
 
         log::debug!(
@@ -282,29 +695,33 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 4;
+        Ok(())
     }
 
-    fn not(&mut self) {
+    fn not(&mut self) -> Result<(), VmError> {
         self.log_opcode("not");
 
-        let register = self.get_register(self.pointer + 1);
-        let a = self.read_value(self.pointer + 2);
+        let register = self.get_register(self.pointer + 1)?;
+        let a = self.read_value(self.pointer + 2)?;
         let value = modulo(!a);
 
         log::debug!("\tsetting register {} to !{} = {}", register, a, value);
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 3;
+        Ok(())
     }
 
-    fn rmem(&mut self) {
+    fn rmem(&mut self) -> Result<(), VmError> {
         self.log_opcode("rmem");
 
-        let register = self.get_register(self.pointer + 1);
-        let address = self.read_value(self.pointer + 2);
-        let value = self.read_value(address);
+        let register = self.get_register(self.pointer + 1)?;
+        let address = self.read_value(self.pointer + 2)?;
+        let value = self.read_memory(address)?;
+        self.watchpoints
+            .read(WatchTarget::Memory(address), value, self.pointer);
 
         log::debug!(
             "\treading memory from address {} and storing in register {}, value is {}",
@@ -313,15 +730,16 @@ impl VM {
             value
         );
 
-        self.registers[register] = value;
+        self.set_register(register, value);
         self.pointer += 3;
+        Ok(())
     }
 
-    fn wmem(&mut self) {
+    fn wmem(&mut self) -> Result<(), VmError> {
         self.log_opcode("wmem");
 
-        let address = self.read_value(self.pointer + 1);
-        let value = self.read_value(self.pointer + 2);
+        let address = self.read_value(self.pointer + 1)?;
+        let value = self.read_value(self.pointer + 2)?;
 
         log::debug!(
             "\twriting memory address {} and storing value {}",
@@ -329,33 +747,53 @@ impl VM {
             value
         );
 
-        self.memory[address] = value;
+        self.write_memory(address, value)?;
         self.pointer += 3;
+        Ok(())
     }
 
-    fn call(&mut self) {
+    fn call(&mut self) -> Result<(), VmError> {
         self.log_opcode("call");
 
-        let jump_to = self.read_value(self.pointer + 1);
+        let jump_to = self.read_value(self.pointer + 1)?;
         let original_next = self.pointer + 2;
-        self.stack.push(original_next);
 
+        if let Some(mut hook) = self.hooks.take(jump_to) {
+            log::debug!("\tcalling the hook at {}", jump_to);
+            let result = hook(&mut HookContext::new(self));
+            self.hooks.insert(jump_to, hook);
+            result?;
+            self.pointer = original_next;
+            return Ok(());
+        }
+
+        self.push_stack(original_next);
         self.pointer = jump_to;
+        Ok(())
     }
 
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<Option<ExitReason>, VmError> {
         self.log_opcode("ret");
-        let jumpt_to = self.stack.pop().unwrap();
+        if self.stack.is_empty() {
+            log::debug!("\tstack is empty, halting");
+            return Ok(Some(ExitReason::Halted));
+        }
+        let jumpt_to = self.pop_stack()?;
 
         log::debug!("\tjumping to {}", jumpt_to);
         self.pointer = jumpt_to;
+        Ok(None)
     }
 
-    fn out(&mut self) {
+    fn out(&mut self) -> Result<(), VmError> {
         self.log_opcode("out");
 
-        let char_code = self.read_value(self.pointer + 1).try_into().unwrap();
-        let char = char::from_u32(char_code).unwrap();
+        let char_code = self.read_value(self.pointer + 1)?;
+        let char = char_code
+            .try_into()
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.error(VmErrorKind::InvalidChar { code: char_code }))?;
         log::debug!(
             "\tchar {} (code {}) at {}",
             char,
@@ -364,33 +802,116 @@ impl VM {
         );
         //        log::info!("char {} (code {}) at {}:", char, char_code, self.pointer + 1);
 
-        print!("{}", char);
+        self.output_char(char)?;
 
-        self.pointer += 2
+        self.pointer += 2;
+        Ok(())
+    }
+
+    pub(crate) fn output_char(&mut self, c: char) -> Result<(), VmError> {
+        self.io
+            .write_char(c)
+            .map_err(|e| self.error(VmErrorKind::Io(e.kind())))?;
+        self.effects.push(Effect::Output(c));
+        Ok(())
     }
 
-    fn r#in(&mut self) {
+    fn r#in(&mut self) -> Result<(), VmError> {
         self.log_opcode("in");
 
+        let target = self.get_value(self.pointer + 1)?;
+
         if self.input_buffer.is_empty() {
-            let mut buffer = String::new();
-            let stdin = io::stdin(); // We get `Stdin` here.
-            stdin.read_line(&mut buffer).unwrap();
+            let buffer = match self.read_line()? {
+                Some(buffer) => buffer,
+                // a snapshot was loaded, continue from its pointer
+                None => return Ok(()),
+            };
 
             //            println!("read: {}", buffer);
 
-            self.input_buffer = buffer.chars().map(|c| (c as usize)).collect();
+            if let Some(c) = buffer.chars().find(|&c| c as usize >= 32768) {
+                return Err(self.error(VmErrorKind::InvalidChar { code: c as usize }));
+            }
+
+            self.input_buffer = buffer.chars().map(|c| c as usize).collect();
             self.input_buffer.reverse();
         }
 
         //      println!("input buffer: {:?}, pop() into {:?}", self.input_buffer, self.get_value(self.pointer + 1));
 
-        match self.get_value(self.pointer + 1) {
-            Number(addr) => self.memory[addr] = self.input_buffer.pop().unwrap(),
-            Register(r) => self.registers[r] = self.input_buffer.pop().unwrap(),
+        let value = self
+            .input_buffer
+            .pop()
+            .ok_or_else(|| self.error(VmErrorKind::InputExhausted))?;
+        self.effects.push(Effect::Input(value));
+        match target {
+            Number(addr) => self.write_memory(addr, value)?,
+            Register(r) => self.set_register(r, value),
         }
 
         self.pointer += 2;
+        Ok(())
+    }
+
+    /// Reads the next line that is meant for the program, handling the
+    /// meta-commands before it. Returns `None` when a snapshot was restored.
+    fn read_line(&mut self) -> Result<Option<String>, VmError> {
+        loop {
+            let line = self
+                .io
+                .read_line()
+                .map_err(|e| self.error(VmErrorKind::Io(e.kind())))?
+                .ok_or_else(|| self.error(VmErrorKind::InputExhausted))?;
+
+            match MetaCommand::parse(&line) {
+                None => return Ok(Some(line)),
+                Some(Ok(command)) => {
+                    if self.execute_meta(command)? {
+                        return Ok(None);
+                    }
+                }
+                Some(Err(message)) => self.write_str(&format!("{}\n", message))?,
+            }
+        }
+    }
+
+    /// Returns whether the state of the VM was replaced.
+    fn execute_meta(&mut self, command: MetaCommand) -> Result<bool, VmError> {
+        match command {
+            MetaCommand::Save(name) => {
+                let path = self.snapshot_dir.join(format!("{}.snap", name));
+                let message = match self.snapshot().save(&path) {
+                    Ok(()) => format!("Saved snapshot '{}'.\n", name),
+                    Err(e) => format!("Failed to save snapshot '{}': {}\n", name, e),
+                };
+                self.write_str(&message)?;
+                Ok(false)
+            }
+            MetaCommand::Load(name) => {
+                let path = self.snapshot_dir.join(format!("{}.snap", name));
+                match Snapshot::load(&path) {
+                    Ok(snapshot) => {
+                        self.restore(&snapshot);
+                        self.write_str(&format!("Restored snapshot '{}'.\n", name))?;
+                        Ok(true)
+                    }
+                    Err(e) => {
+                        self.write_str(&format!("Failed to load snapshot '{}': {}\n", name, e))?;
+                        Ok(false)
+                    }
+                }
+            }
+        }
+    }
+
+    fn write_str(&mut self, text: &str) -> Result<(), VmError> {
+        for c in text.chars() {
+            self.io
+                .write_char(c)
+                .map_err(|e| self.error(VmErrorKind::Io(e.kind())))?;
+        }
+        Ok(())
     }
 
     fn noop(&mut self) {
@@ -398,48 +919,629 @@ impl VM {
         self.pointer += 1
     }
 
-    fn unimplemented(&mut self) {
-        panic!("umimplemented: {}", self.read_value_at_pointer())
+    fn unimplemented(&self, addr: usize, op_code: usize) -> VmError {
+        self.error(VmErrorKind::InvalidOpcode {
+            addr,
+            opcode: op_code,
+        })
     }
 
-    fn read_value_at_pointer(&self) -> usize {
-        self.read_value(self.pointer)
+    fn error(&self, kind: VmErrorKind) -> VmError {
+        VmError {
+            kind,
+            pointer: self.pointer,
+            registers: self.registers,
+        }
     }
 
-    fn read_value(&self, pointer: usize) -> usize {
-        match self.get_value(pointer) {
-            Number(n) => n,
-            Register(r) => self.registers[r],
+    fn read_value(&mut self, pointer: usize) -> Result<usize, VmError> {
+        match self.get_value(pointer)? {
+            Number(n) => Ok(n),
+            Register(r) => {
+                let value = self.registers[r];
+                self.watchpoints
+                    .read(WatchTarget::Register(r), value, self.pointer);
+                Ok(value)
+            }
         }
     }
 
-    fn get_register(&self, pointer: usize) -> usize {
-        match self.get_value(pointer) {
-            Number(_) => panic!("Expected a register but got number"),
-            Register(r) => r,
+    fn get_register(&self, pointer: usize) -> Result<usize, VmError> {
+        match self.get_value(pointer)? {
+            Number(raw) => Err(self.error(VmErrorKind::ExpectedRegister { addr: pointer, raw })),
+            Register(r) => Ok(r),
         }
     }
 
-    fn get_value(&self, pointer: usize) -> Value {
-        let value = self.memory[pointer];
-        if value < 32768 {
-            log::debug!("value is number: {} at {}", value, pointer);
-            Number(value)
-        } else if (32768..32776).contains(&value) {
-            let index: usize = modulo(value);
-            Register(index)
+    fn get_value(&self, pointer: usize) -> Result<Value, VmError> {
+        let value = self.read_memory(pointer)?;
+        let decoded = Value::decode(value).ok_or_else(|| {
+            self.error(VmErrorKind::InvalidValue {
+                addr: pointer,
+                raw: value,
+            })
+        })?;
+        if let Number(n) = decoded {
+            log::debug!("value is number: {} at {}", n, pointer);
+        }
+        Ok(decoded)
+    }
+
+    pub(crate) fn set_register(&mut self, register: usize, value: usize) {
+        self.effects.push(Effect::RegisterWrite {
+            register,
+            old: self.registers[register],
+            new: value,
+        });
+        self.watchpoints.write(
+            WatchTarget::Register(register),
+            self.registers[register],
+            value,
+            self.pointer,
+        );
+        self.registers[register] = value;
+    }
+
+    pub(crate) fn read_memory(&self, address: usize) -> Result<usize, VmError> {
+        self.check_address(address)?;
+        Ok(self.memory[address].into())
+    }
+
+    pub(crate) fn write_memory(&mut self, address: usize, value: usize) -> Result<(), VmError> {
+        self.check_address(address)?;
+        let word = value.try_into().map_err(|_| {
+            self.error(VmErrorKind::InvalidValue {
+                addr: address,
+                raw: value,
+            })
+        })?;
+
+        self.effects.push(Effect::MemoryWrite {
+            addr: address,
+            old: self.memory[address].into(),
+            new: value,
+        });
+        self.watchpoints.write(
+            WatchTarget::Memory(address),
+            self.memory[address].into(),
+            value,
+            self.pointer,
+        );
+        self.memory[address] = word;
+        Ok(())
+    }
+
+    /// Addresses are 15-bit values, anything outside of the memory is invalid.
+    fn check_address(&self, address: usize) -> Result<(), VmError> {
+        if address < MEMORY_SIZE {
+            Ok(())
         } else {
-            panic!("illegal value: {} at {}", value, pointer)
+            Err(self.error(VmErrorKind::OutOfBoundsAddress { addr: address }))
         }
     }
+
+    pub(crate) fn push_stack(&mut self, value: usize) {
+        self.effects.push(Effect::StackPush(value));
+        self.stack.push(value);
+    }
+
+    pub(crate) fn pop_stack(&mut self) -> Result<usize, VmError> {
+        let value = self
+            .stack
+            .pop()
+            .ok_or_else(|| self.error(VmErrorKind::StackUnderflow))?;
+        self.effects.push(Effect::StackPop(value));
+        Ok(value)
+    }
 }
 
-#[derive(Debug)]
-enum Value {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
     Number(usize),
     Register(usize),
 }
 
+impl Value {
+    /// Decodes a word of the binary format, returns `None` for invalid words.
+    pub fn decode(word: usize) -> Option<Value> {
+        if word < 32768 {
+            Some(Number(word))
+        } else if (32768..32776).contains(&word) {
+            Some(Register(modulo(word)))
+        } else {
+            None
+        }
+    }
+
+    pub fn encode(self) -> u16 {
+        match self {
+            Number(n) => n as u16,
+            Register(r) => 32768 + r as u16,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number(n) => write!(f, "{}", n),
+            Register(r) => write!(f, "r{}", r),
+        }
+    }
+}
+
 fn modulo(number: usize) -> usize {
     number % 32768
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Scripted;
+
+    const R0: u16 = 32768;
+    const R1: u16 = 32769;
+    const R2: u16 = 32770;
+    const R7: u16 = 32775;
+
+    fn boot(program: &[u16]) -> (VM, Scripted) {
+        let io = Scripted::new();
+        let vm = VM::boot(io.clone()).load_program(program.to_vec()).unwrap();
+        (vm, io)
+    }
+
+    fn run(program: &[u16]) -> (VM, Scripted) {
+        let (mut vm, io) = boot(program);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        (vm, io)
+    }
+
+    #[test]
+    fn arch_spec_example() {
+        let (mut vm, io) = boot(&[9, R0, R1, 4, 19, R0]);
+        vm.registers[1] = 61;
+
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 65);
+        assert_eq!(io.output(), "A");
+    }
+
+    #[test]
+    fn halt_stops_execution() {
+        let (vm, io) = run(&[0, 19, 65]);
+
+        assert_eq!(vm.pointer, 0);
+        assert_eq!(io.output(), "");
+    }
+
+    #[test]
+    fn set_register_from_literal_and_register() {
+        let (vm, _) = run(&[1, R0, 42, 1, R7, R0, 0]);
+
+        assert_eq!(vm.registers[0], 42);
+        assert_eq!(vm.registers[7], 42);
+    }
+
+    #[test]
+    fn set_requires_a_register() {
+        let (mut vm, _) = boot(&[1, 5, 42]);
+
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmErrorKind::ExpectedRegister { addr: 1, raw: 5 }
+        );
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let (vm, _) = run(&[1, R0, 7, 2, R0, 2, 3, 3, R1, 0]);
+
+        assert_eq!(vm.registers[1], 3);
+        assert_eq!(vm.stack, vec![7]);
+    }
+
+    #[test]
+    fn pop_on_empty_stack_is_an_error() {
+        let (mut vm, _) = boot(&[3, R0]);
+
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::StackUnderflow);
+        assert_eq!(error.pointer, 0);
+    }
+
+    #[test]
+    fn eq() {
+        let (vm, _) = run(&[1, R1, 5, 4, R0, R1, 5, 4, R2, R1, 6, 0]);
+
+        assert_eq!(vm.registers[0], 1);
+        assert_eq!(vm.registers[2], 0);
+    }
+
+    #[test]
+    fn gt() {
+        let (vm, _) = run(&[1, R1, 5, 5, R0, R1, 4, 5, R2, 4, R1, 0]);
+
+        assert_eq!(vm.registers[0], 1);
+        assert_eq!(vm.registers[2], 0);
+    }
+
+    #[test]
+    fn jmp() {
+        let (vm, _) = run(&[6, 3, 0, 1, R0, 1, 0]);
+
+        assert_eq!(vm.registers[0], 1);
+    }
+
+    #[test]
+    fn jmp_to_register() {
+        let (vm, _) = run(&[1, R0, 6, 6, R0, 0, 1, R1, 1, 0]);
+
+        assert_eq!(vm.registers[1], 1);
+    }
+
+    #[test]
+    fn jt() {
+        let (vm, _) = run(&[7, 0, 6, 7, 1, 9, 0, 0, 0, 1, R0, 1, 0]);
+
+        assert_eq!(vm.registers[0], 1);
+    }
+
+    #[test]
+    fn jf() {
+        let (vm, _) = run(&[8, 1, 6, 8, R0, 9, 0, 0, 0, 1, R1, 1, 0]);
+
+        assert_eq!(vm.registers[1], 1);
+    }
+
+    #[test]
+    fn add_wraps_around() {
+        let (vm, _) = run(&[9, R0, 32758, 15, 0]);
+
+        assert_eq!(vm.registers[0], 5);
+    }
+
+    #[test]
+    fn mult_wraps_around() {
+        let (vm, _) = run(&[10, R0, 16384, 3, 10, R1, R0, R0, 0]);
+
+        assert_eq!(vm.registers[0], 16384);
+        assert_eq!(vm.registers[1], 0);
+    }
+
+    #[test]
+    fn r#mod() {
+        let (vm, _) = run(&[11, R0, 17, 5, 0]);
+
+        assert_eq!(vm.registers[0], 2);
+    }
+
+    #[test]
+    fn mod_by_zero_is_an_error() {
+        let (mut vm, _) = boot(&[1, R1, 0, 11, R0, 5, R1, 0]);
+
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.kind, VmErrorKind::DivisionByZero { addr: 3 });
+        assert_eq!(error.pointer, 3);
+    }
+
+    #[test]
+    fn poke_register_checks_its_arguments() {
+        let (mut vm, _) = boot(&[0]);
+
+        assert_eq!(
+            vm.poke_register(8, 1).unwrap_err().kind,
+            VmErrorKind::InvalidRegister { register: 8 }
+        );
+        assert_eq!(
+            vm.poke_register(0, 32768).unwrap_err().kind,
+            VmErrorKind::WordOutOfRange { value: 32768 }
+        );
+        vm.poke_register(7, 32767).unwrap();
+        assert_eq!(vm.registers[7], 32767);
+    }
+
+    #[test]
+    fn and_or() {
+        let (vm, _) = run(&[12, R0, 12, 10, 13, R1, 12, 10, 0]);
+
+        assert_eq!(vm.registers[0], 8);
+        assert_eq!(vm.registers[1], 14);
+    }
+
+    #[test]
+    fn not_is_15_bit() {
+        let (vm, _) = run(&[14, R0, 0, 14, R1, 0b101010101010101, 0]);
+
+        assert_eq!(vm.registers[0], 32767);
+        assert_eq!(vm.registers[1], 0b010101010101010);
+    }
+
+    #[test]
+    fn rmem_reads_raw_memory() {
+        let (vm, _) = run(&[1, R1, 8, 15, R0, R1, 0, 21, R2]);
+
+        assert_eq!(vm.registers[0], R2 as usize);
+    }
+
+    #[test]
+    fn wmem_writes_beyond_the_program() {
+        let (vm, _) = run(&[1, R0, 30000, 16, R0, 42, 16, 32767, R0, 0]);
+
+        assert_eq!(vm.memory[30000], 42);
+        assert_eq!(vm.memory[32767], 30000);
+    }
+
+    #[test]
+    fn wmem_to_out_of_bounds_address() {
+        let (mut vm, _) = boot(&[15, R0, 5, 16, R0, 32768]);
+
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmErrorKind::OutOfBoundsAddress { addr: 32768 }
+        );
+    }
+
+    #[test]
+    fn call_and_ret() {
+        let (vm, io) = run(&[17, 5, 19, 66, 0, 19, 65, 18]);
+
+        assert_eq!(io.output(), "AB");
+        assert_eq!(vm.pointer, 4);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[test]
+    fn call_pushes_next_instruction() {
+        let (mut vm, _) = boot(&[21, 17, R0]);
+        vm.registers[0] = 10;
+
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.stack, vec![3]);
+        assert_eq!(vm.pointer, 10);
+    }
+
+    #[test]
+    fn ret_on_empty_stack_halts() {
+        let (vm, io) = run(&[18, 19, 65]);
+
+        assert_eq!(io.output(), "");
+        assert_eq!(vm.pointer, 0);
+        assert_eq!(vm.state(), VmState::Halted);
+    }
+
+    #[test]
+    fn run_for_stops_after_max_steps() {
+        let (mut vm, _) = boot(&[21, 6, 0]);
+
+        assert_eq!(vm.run_for(10), Ok(ExitReason::StepLimit));
+        assert_eq!(vm.steps(), 10);
+    }
+
+    #[test]
+    fn halted_vm_keeps_its_final_state() {
+        let (mut vm, _) = run(&[1, R0, 5, 16, 200, R0, 0]);
+
+        assert_eq!(vm.state(), VmState::Halted);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.step().unwrap_err().kind, VmErrorKind::AlreadyHalted);
+        assert_eq!(vm.pointer(), 6);
+        assert_eq!(vm.memory()[200], 5);
+
+        let mut dump = vec![];
+        vm.dump_memory(&mut dump).unwrap();
+        assert_eq!(dump.len(), MEMORY_SIZE * 2);
+        assert_eq!(&dump[400..402], &[5, 0]);
+    }
+
+    #[test]
+    fn out_literal_and_register() {
+        let (_, io) = run(&[1, R2, 105, 19, 72, 19, R2, 19, 10, 0]);
+
+        assert_eq!(io.output(), "Hi\n");
+    }
+
+    #[test]
+    fn r#in_reads_a_whole_line() {
+        let (mut vm, io) = boot(&[20, R0, 20, 100, 20, R7, 0]);
+        io.push_line("ab");
+
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 'a' as usize);
+        assert_eq!(vm.memory[100], 'b' as u16);
+        assert_eq!(vm.registers[7], '\n' as usize);
+    }
+
+    #[test]
+    fn r#in_without_input() {
+        let (mut vm, _) = boot(&[20, R0]);
+
+        assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::InputExhausted);
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let (mut vm, io) = boot(&[20, R0, 20, R1, 19, R0, 19, R1, 0]);
+        io.push_line("xy");
+        vm.step().unwrap();
+        let snapshot = vm.snapshot();
+        vm.run().unwrap();
+
+        vm.restore(&snapshot);
+        assert_eq!(vm.state(), VmState::Running);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(io.output(), "xyxy");
+    }
+
+    #[test]
+    fn save_and_load_meta_commands() {
+        let dir = std::env::temp_dir().join(format!("synacor-vm-{}", std::process::id()));
+        let (mut vm, io) = boot(&[20, R0, 19, R0, 6, 0]);
+        vm.set_snapshot_dir(&dir);
+        io.push_line("!save start");
+        io.push_line("a");
+        io.push_line("!load start");
+        io.push_line("b");
+
+        assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::InputExhausted);
+        assert_eq!(
+            io.output(),
+            "Saved snapshot 'start'.\na\nRestored snapshot 'start'.\nb\n"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn watchpoints() {
+        use crate::watch::Access;
+
+        let (mut vm, _) = boot(&[9, R0, R7, 1, 16, 50, R0, 15, R1, 50, 0]);
+        let reg = vm
+            .watchpoints_mut()
+            .add(WatchTarget::Register(7), Access::Read, None);
+        let write = vm
+            .watchpoints_mut()
+            .add(WatchTarget::Memory(50), Access::Write, Some(1));
+        let read = vm
+            .watchpoints_mut()
+            .add(WatchTarget::Memory(50), Access::Read, None);
+
+        let hits: Vec<Vec<usize>> = (0..3)
+            .map(|_| {
+                let event = vm.step().unwrap();
+                event.watch_hits.iter().map(|hit| hit.id).collect()
+            })
+            .collect();
+        assert_eq!(hits, vec![vec![reg], vec![write], vec![read]]);
+    }
+
+    #[test]
+    fn hooks_replace_subroutines() {
+        // 0: call 5; 2: out r0; 4: halt; 5: halt
+        let (mut vm, io) = boot(&[17, 5, 19, R0, 0, 0]);
+        vm.add_hook(5, |context| {
+            let value = context.read(0)? + context.register(1);
+            context.set_register(0, value);
+            context.output('>')
+        });
+        vm.poke_register(1, 48).unwrap();
+
+        let event = vm.step().unwrap();
+        assert_eq!(event.next_pointer, 2);
+        assert_eq!(
+            event.effects,
+            vec![
+                Effect::RegisterWrite {
+                    register: 0,
+                    old: 0,
+                    new: 65
+                },
+                Effect::Output('>')
+            ]
+        );
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert!(vm.stack().is_empty());
+        assert_eq!(io.output(), ">A");
+
+        assert!(vm.remove_hook(5));
+        assert!(!vm.hooks().contains(5));
+    }
+
+    #[test]
+    fn noop() {
+        let (vm, _) = run(&[21, 21, 0]);
+
+        assert_eq!(vm.pointer, 2);
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let (mut vm, _) = boot(&[21, 22]);
+
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmErrorKind::InvalidOpcode {
+                addr: 1,
+                opcode: 22
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_operand_value() {
+        let (mut vm, _) = boot(&[19, 32776]);
+
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmErrorKind::InvalidValue {
+                addr: 1,
+                raw: 32776
+            }
+        );
+    }
+
+    #[test]
+    fn running_off_the_end_of_memory() {
+        let mut program = vec![21; MEMORY_SIZE];
+        program[0] = 6;
+        program[1] = 32767;
+        let (mut vm, _) = boot(&program);
+
+        assert_eq!(
+            vm.run().unwrap_err().kind,
+            VmErrorKind::OutOfBoundsAddress { addr: 32768 }
+        );
+    }
+
+    #[test]
+    fn program_too_large() {
+        let result = VM::boot(Scripted::new()).load_program(vec![0; MEMORY_SIZE + 1]);
+
+        assert_eq!(
+            result.unwrap_err().kind,
+            VmErrorKind::ProgramTooLarge {
+                len: MEMORY_SIZE + 1
+            }
+        );
+    }
+
+    #[test]
+    fn register_operands_in_every_position() {
+        let (vm, _) = run(&[
+            1, R0, 10, 1, R1, 3, 9, R2, R0, R1, 10, R7, R2, R1, 11, R1, R7, R0, 0,
+        ]);
+
+        assert_eq!(vm.registers[2], 13);
+        assert_eq!(vm.registers[7], 39);
+        assert_eq!(vm.registers[1], 9);
+    }
+
+    #[test]
+    fn step_reports_effects() {
+        let (mut vm, _) = boot(&[9, R0, R1, 4, 19, R0]);
+        vm.registers[1] = 61;
+
+        let event = vm.step().unwrap();
+        assert_eq!(event.index, 0);
+        assert_eq!(event.pointer, 0);
+        assert_eq!(event.next_pointer, 4);
+        assert_eq!(
+            event.instruction,
+            Instruction {
+                opcode: Opcode::Add,
+                operands: vec![Register(0), Register(1), Number(4)],
+            }
+        );
+        assert_eq!(
+            event.effects,
+            vec![Effect::RegisterWrite {
+                register: 0,
+                old: 0,
+                new: 65
+            }]
+        );
+
+        let event = vm.step().unwrap();
+        assert_eq!(event.output().collect::<String>(), "A");
+    }
+}
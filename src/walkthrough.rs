@@ -0,0 +1,407 @@
+//! Scripted playthroughs with assertions on the output.
+//!
+//! A walkthrough script holds one directive per line:
+//!
+//! ```text
+//! # comments and blank lines are ignored
+//! > take tablet            a line of input for the program
+//! ? Taken.                 the output contains the text
+//! ~ writing "(\w{12})"     the output matches the regex, groups are captured
+//! | What do you do?        adjacent lines must appear in the output as is
+//! ```
+//!
+//! The expectations are checked against the output printed since the
+//! previous input line, once the program waits for more input or halts.
+
+use crate::{
+    device::Scripted,
+    vm::{ExitReason, VmError, VmErrorKind, VM},
+};
+use regex::{Regex, RegexBuilder};
+use std::{error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Walkthrough {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Input(usize, String),
+    Expect(usize, Expectation),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expectation {
+    Contains(String),
+    /// `^` and `$` match at line boundaries.
+    Matches(Regex),
+    Lines(Vec<String>),
+}
+
+impl PartialEq for Expectation {
+    fn eq(&self, other: &Expectation) -> bool {
+        match (self, other) {
+            (Expectation::Contains(a), Expectation::Contains(b)) => a == b,
+            (Expectation::Matches(a), Expectation::Matches(b)) => a.as_str() == b.as_str(),
+            (Expectation::Lines(a), Expectation::Lines(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Expectation {}
+
+/// A group captured by a regex expectation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub line: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub inputs: usize,
+    pub expectations: usize,
+    pub captures: Vec<Capture>,
+    pub halted: bool,
+}
+
+#[derive(Debug)]
+pub enum WalkthroughError {
+    Syntax {
+        line: usize,
+        message: String,
+    },
+    /// The output did not meet the expectation, with a diff of the expected
+    /// and the actual output.
+    Mismatch {
+        line: usize,
+        diff: String,
+    },
+    /// The program halted before reading the input at the line.
+    Halted {
+        line: usize,
+    },
+    Vm(VmError),
+}
+
+impl fmt::Display for WalkthroughError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalkthroughError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            WalkthroughError::Mismatch { line, diff } => {
+                write!(f, "line {}: unexpected output\n{}", line, diff)
+            }
+            WalkthroughError::Halted { line } => {
+                write!(f, "line {}: the program halted before this input", line)
+            }
+            WalkthroughError::Vm(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WalkthroughError {}
+
+impl From<VmError> for WalkthroughError {
+    fn from(e: VmError) -> Self {
+        WalkthroughError::Vm(e)
+    }
+}
+
+impl Walkthrough {
+    pub fn parse(source: &str) -> Result<Walkthrough, WalkthroughError> {
+        let mut steps = Vec::new();
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            if text.trim().is_empty() || text.starts_with('#') {
+                continue;
+            }
+            let len = text.chars().next().map_or(0, char::len_utf8);
+            let (directive, rest) = text.split_at(len);
+            let rest = rest.strip_prefix(' ').unwrap_or(rest);
+            let step = match directive {
+                ">" => Step::Input(line, rest.to_string()),
+                "?" => Step::Expect(line, Expectation::Contains(rest.to_string())),
+                "~" => {
+                    let regex = RegexBuilder::new(rest)
+                        .multi_line(true)
+                        .build()
+                        .map_err(|e| WalkthroughError::Syntax {
+                            line,
+                            message: e.to_string(),
+                        })?;
+                    Step::Expect(line, Expectation::Matches(regex))
+                }
+                "|" => {
+                    if let Some(Step::Expect(start, Expectation::Lines(lines))) = steps.last_mut() {
+                        if *start + lines.len() == line {
+                            lines.push(rest.to_string());
+                            continue;
+                        }
+                    }
+                    Step::Expect(line, Expectation::Lines(vec![rest.to_string()]))
+                }
+                _ => {
+                    return Err(WalkthroughError::Syntax {
+                        line,
+                        message: format!("unknown directive: {}", directive),
+                    })
+                }
+            };
+            steps.push(step);
+        }
+
+        Ok(Walkthrough { steps })
+    }
+
+    /// Plays the walkthrough on a VM that reads from and writes to `io`,
+    /// stopping at the first unmet expectation.
+    pub fn run(&self, vm: &mut VM, io: &Scripted) -> Result<Report, WalkthroughError> {
+        let mut report = Report {
+            inputs: 0,
+            expectations: 0,
+            captures: vec![],
+            halted: false,
+        };
+        let mut pending: Vec<(usize, &Expectation)> = Vec::new();
+
+        for step in &self.steps {
+            match step {
+                Step::Expect(line, expectation) => pending.push((*line, expectation)),
+                Step::Input(line, input) => {
+                    let output = run_until_input(vm, io, &mut report)?;
+                    check(&pending, &output, &mut report)?;
+                    pending.clear();
+                    if report.halted {
+                        return Err(WalkthroughError::Halted { line: *line });
+                    }
+                    io.push_line(input.as_str());
+                    report.inputs += 1;
+                }
+            }
+        }
+
+        let output = run_until_input(vm, io, &mut report)?;
+        check(&pending, &output, &mut report)?;
+        Ok(report)
+    }
+}
+
+/// Runs the program until it waits for input that was not given yet or
+/// halts, returning its output.
+fn run_until_input(
+    vm: &mut VM,
+    io: &Scripted,
+    report: &mut Report,
+) -> Result<String, WalkthroughError> {
+    if !report.halted {
+        match vm.run() {
+            Ok(ExitReason::Halted) => report.halted = true,
+            Ok(ExitReason::StepLimit) => {}
+            Err(e) if e.kind == VmErrorKind::InputExhausted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(io.take_output())
+}
+
+fn check(
+    pending: &[(usize, &Expectation)],
+    output: &str,
+    report: &mut Report,
+) -> Result<(), WalkthroughError> {
+    let actual: Vec<&str> = output.lines().collect();
+
+    for (line, expectation) in pending {
+        let expected: Vec<String> = match expectation {
+            Expectation::Contains(text) => {
+                if output.contains(text.as_str()) {
+                    report.expectations += 1;
+                    continue;
+                }
+                text.lines().map(String::from).collect()
+            }
+            Expectation::Matches(regex) => match regex.captures(output) {
+                Some(groups) => {
+                    report
+                        .captures
+                        .extend(groups.iter().skip(1).flatten().map(|group| Capture {
+                            line: *line,
+                            text: group.as_str().to_string(),
+                        }));
+                    report.expectations += 1;
+                    continue;
+                }
+                None => vec![format!("/{}/", regex)],
+            },
+            Expectation::Lines(lines) => {
+                if actual
+                    .windows(lines.len())
+                    .any(|window| window.iter().zip(lines).all(|(a, b)| a == b))
+                {
+                    report.expectations += 1;
+                    continue;
+                }
+                lines.clone()
+            }
+        };
+        let expected: Vec<&str> = expected.iter().map(String::as_str).collect();
+        return Err(WalkthroughError::Mismatch {
+            line: *line,
+            diff: diff(&expected, &actual),
+        });
+    }
+    Ok(())
+}
+
+/// A line diff based on the longest common subsequence, lines only expected
+/// are prefixed with `-`, lines only in the output with `+`.
+pub fn diff(expected: &[&str], actual: &[&str]) -> String {
+    let (n, m) = (expected.len(), actual.len());
+    // lengths[i][j]: longest common subsequence of expected[i..] and actual[j..]
+    let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if expected[i] == actual[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            diff.push_str(&format!("  {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lengths[i + 1][j] >= lengths[i][j + 1]) {
+            diff.push_str(&format!("- {}\n", expected[i]));
+            i += 1;
+        } else {
+            diff.push_str(&format!("+ {}\n", actual[j]));
+            j += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary;
+    use std::fs;
+
+    const R0: u16 = 32768;
+
+    // echoes every line: 0: in r0; 2: out r0; 4: jmp 0
+    const ECHO: [u16; 6] = [20, R0, 19, R0, 6, 0];
+
+    fn play(script: &str, program: Vec<u16>) -> Result<Report, WalkthroughError> {
+        let io = Scripted::new();
+        let mut vm = VM::boot(io.clone()).load_program(program).unwrap();
+        Walkthrough::parse(script).unwrap().run(&mut vm, &io)
+    }
+
+    #[test]
+    fn parse() {
+        let walkthrough = Walkthrough::parse("# intro\n> a\n? b\n| c\n|\n~ (d)\n").unwrap();
+        assert_eq!(walkthrough.steps.len(), 4);
+        assert_eq!(
+            walkthrough.steps[2],
+            Step::Expect(4, Expectation::Lines(vec!["c".to_string(), "".to_string()]))
+        );
+        assert_eq!(Walkthrough::parse("| a\n\n| b").unwrap().steps.len(), 2);
+        assert!(matches!(
+            Walkthrough::parse("> a\n~ (b"),
+            Err(WalkthroughError::Syntax { line: 2, .. })
+        ));
+        assert!(Walkthrough::parse("! a").is_err());
+        assert!(matches!(
+            Walkthrough::parse("> a\n€ oops"),
+            Err(WalkthroughError::Syntax { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn checks_the_output_of_each_input() {
+        let report = play(
+            "> hello\n? ell\n| hello\n> code 1234\n~ code (\\d+)",
+            ECHO.to_vec(),
+        );
+        assert_eq!(
+            report.unwrap(),
+            Report {
+                inputs: 2,
+                expectations: 3,
+                captures: vec![Capture {
+                    line: 5,
+                    text: "1234".to_string()
+                }],
+                halted: false,
+            }
+        );
+
+        match play("> hello\n| hallo", ECHO.to_vec()) {
+            Err(WalkthroughError::Mismatch { line: 2, diff }) => {
+                assert_eq!(diff, "- hallo\n+ hello\n")
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(matches!(
+            play("> a\n> b", vec![20, R0, 0]),
+            Err(WalkthroughError::Halted { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn regexes_on_long_output() {
+        let walkthrough = Walkthrough::parse("~ .*b\n~ ^(c)$").unwrap();
+        let pending: Vec<(usize, &Expectation)> = walkthrough
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::Expect(line, expectation) => Some((*line, expectation)),
+                Step::Input(..) => None,
+            })
+            .collect();
+        let mut report = Report {
+            inputs: 0,
+            expectations: 0,
+            captures: vec![],
+            halted: false,
+        };
+        let output = format!("{}b\nc\n", "a".repeat(200_000));
+
+        check(&pending, &output, &mut report).unwrap();
+        assert_eq!(report.expectations, 2);
+        assert_eq!(report.captures[0].text, "c");
+    }
+
+    #[test]
+    fn diff_lines() {
+        assert_eq!(
+            diff(&["a", "b", "c"], &["a", "x", "c", "d"]),
+            "  a\n- b\n+ x\n  c\n+ d\n"
+        );
+    }
+
+    #[test]
+    fn opening_of_the_challenge() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let script = fs::read_to_string(format!("{}/walkthroughs/opening.txt", dir)).unwrap();
+        let program = binary::challenge();
+
+        let report = play(&script, program).unwrap();
+        let codes: Vec<&str> = report
+            .captures
+            .iter()
+            .map(|capture| capture.text.as_str())
+            .collect();
+        assert_eq!(codes, vec!["ggZNxmyaPzLw", "WXIHIdEBKRGz", "tEuuRLXSEkHh"]);
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchTarget {
+    Memory(usize),
+    Register(usize),
+}
+
+/// The kind of access that triggers a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// A write that changes the value.
+    Change,
+    /// Either a read or a write.
+    Any,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub target: WatchTarget,
+    pub access: Access,
+    /// Only trigger when the value read or written equals this one.
+    pub condition: Option<usize>,
+    /// Number of matching accesses still to let through before triggering.
+    pub ignores_left: u64,
+    /// Number of matching accesses so far.
+    pub hits: u64,
+}
+
+/// A triggered watchpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub target: WatchTarget,
+    /// Either `Access::Read` or `Access::Write`.
+    pub access: Access,
+    pub old: usize,
+    pub value: usize,
+    pub pointer: usize,
+}
+
+/// The watchpoints of a VM, checked whenever memory is accessed by `rmem`,
+/// `wmem` and `in`, or a register is read or written.
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    next_id: usize,
+    hits: Vec<WatchHit>,
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, target: WatchTarget, access: Access, condition: Option<usize>) -> usize {
+        self.next_id += 1;
+        self.list.push(Watchpoint {
+            id: self.next_id,
+            target,
+            access,
+            condition,
+            ignores_left: 0,
+            hits: 0,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.list.len();
+        self.list.retain(|watchpoint| watchpoint.id != id);
+        self.list.len() != len
+    }
+
+    pub fn set_ignore_count(&mut self, id: usize, count: u64) -> bool {
+        match self.list.iter_mut().find(|watchpoint| watchpoint.id == id) {
+            Some(watchpoint) => {
+                watchpoint.ignores_left = count;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    pub(crate) fn read(&mut self, target: WatchTarget, value: usize, pointer: usize) {
+        self.check(target, Access::Read, value, value, pointer);
+    }
+
+    pub(crate) fn write(&mut self, target: WatchTarget, old: usize, value: usize, pointer: usize) {
+        self.check(target, Access::Write, old, value, pointer);
+    }
+
+    pub(crate) fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+
+    fn check(
+        &mut self,
+        target: WatchTarget,
+        access: Access,
+        old: usize,
+        value: usize,
+        pointer: usize,
+    ) {
+        for watchpoint in self.list.iter_mut() {
+            let matches = watchpoint.target == target
+                && match (watchpoint.access, access) {
+                    (Access::Any, _) => true,
+                    (Access::Change, Access::Write) => old != value,
+                    (expected, actual) => expected == actual,
+                }
+                && watchpoint
+                    .condition
+                    .is_none_or(|expected| expected == value);
+            if !matches {
+                continue;
+            }
+
+            watchpoint.hits += 1;
+            if watchpoint.ignores_left > 0 {
+                watchpoint.ignores_left -= 1;
+            } else {
+                self.hits.push(WatchHit {
+                    id: watchpoint.id,
+                    target,
+                    access,
+                    old,
+                    value,
+                    pointer,
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Display for WatchTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchTarget::Memory(addr) => write!(f, "mem[{}]", addr),
+            WatchTarget::Register(register) => write!(f, "r{}", register),
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Change => "change",
+            Access::Any => "access",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.id, self.target, self.access)?;
+        if let Some(value) = self.condition {
+            write!(f, " == {}", value)?;
+        }
+        write!(f, " (hits: {}", self.hits)?;
+        if self.ignores_left > 0 {
+            write!(f, ", ignore: {}", self.ignores_left)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(
+                f,
+                "watchpoint {}: {} read {} at {:05}",
+                self.id, self.target, self.value, self.pointer
+            ),
+            _ => write!(
+                f,
+                "watchpoint {}: {} written {} -> {} at {:05}",
+                self.id, self.target, self.old, self.value, self.pointer
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_kinds() {
+        let mut watchpoints = Watchpoints::default();
+        let read = watchpoints.add(WatchTarget::Register(7), Access::Read, None);
+        let change = watchpoints.add(WatchTarget::Register(7), Access::Change, None);
+        watchpoints.add(WatchTarget::Memory(7), Access::Any, None);
+
+        watchpoints.read(WatchTarget::Register(7), 0, 10);
+        watchpoints.write(WatchTarget::Register(7), 0, 0, 11);
+        watchpoints.write(WatchTarget::Register(7), 0, 1, 12);
+
+        let hits: Vec<(usize, usize)> = watchpoints
+            .take_hits()
+            .iter()
+            .map(|hit| (hit.id, hit.pointer))
+            .collect();
+        assert_eq!(hits, vec![(read, 10), (change, 12)]);
+        assert!(watchpoints.take_hits().is_empty());
+    }
+
+    #[test]
+    fn conditions_and_ignore_counts() {
+        let mut watchpoints = Watchpoints::default();
+        let id = watchpoints.add(WatchTarget::Memory(100), Access::Write, Some(5));
+        watchpoints.set_ignore_count(id, 1);
+
+        watchpoints.write(WatchTarget::Memory(100), 0, 4, 1);
+        watchpoints.write(WatchTarget::Memory(100), 4, 5, 2);
+        watchpoints.write(WatchTarget::Memory(100), 5, 5, 3);
+
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].pointer, 3);
+        assert_eq!(watchpoints.list()[0].hits, 2);
+    }
+
+    #[test]
+    fn ignore_after_hits() {
+        let mut watchpoints = Watchpoints::default();
+        let id = watchpoints.add(WatchTarget::Register(0), Access::Read, None);
+        watchpoints.read(WatchTarget::Register(0), 0, 1);
+        assert_eq!(watchpoints.take_hits().len(), 1);
+
+        watchpoints.set_ignore_count(id, 2);
+        for pointer in 2..5 {
+            watchpoints.read(WatchTarget::Register(0), 0, pointer);
+        }
+
+        let hits = watchpoints.take_hits();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].pointer, 4);
+        assert_eq!(watchpoints.list()[0].ignores_left, 0);
+    }
+
+    #[test]
+    fn remove() {
+        let mut watchpoints = Watchpoints::default();
+        let id = watchpoints.add(WatchTarget::Memory(1), Access::Read, None);
+
+        assert!(watchpoints.remove(id));
+        assert!(!watchpoints.remove(id));
+        watchpoints.read(WatchTarget::Memory(1), 0, 0);
+        assert!(watchpoints.take_hits().is_empty());
+    }
+}